@@ -1,41 +1,125 @@
 use {
     bcrypt::BcryptError,
+    serde_json::Error as JSONError,
+    chrono::{
+        DateTime,
+        Utc,
+    },
     sqlx::Error as SqlxError,
+    crate::{
+        token::oauth::OAuthErrorCode,
+        user::password_policy::PolicyViolation,
+    },
     std::io::Error as IOError,
 };
 #[derive(Debug)]
 pub enum RustersError {
+    AccountLocked { until: DateTime<Utc> },
     BcryptError(BcryptError),
+    ConsumerNameTakenError,
+    InactiveConsumerError,
+    InsufficientScope(Vec<String>),
     InvalidCredentialsError,
+    InvalidMaxUsesError,
+    InvalidSecondFactorError,
+    InvalidTokenError,
     IOError(IOError),
+    JSONError(JSONError),
+    MasterKeyError,
+    MissingEmailError,
     NotLoggedInError,
+    OAuthError(OAuthErrorCode),
     SQLError(SqlxError),
+    TokenExhaustedError,
+    TokenReuseError,
     NoSessionError,
+    NoSigningKeyError,
+    SecondFactorRequiredError,
+    WeakPassword(Vec<PolicyViolation>),
 }
 impl std::fmt::Display for RustersError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
+            RustersError::AccountLocked { until } => {
+                let msg = &format!("Account is locked until {}", until);
+                f.write_str(msg)
+            },
             RustersError::BcryptError(e) => {
                 let msg = &format!("{}", e);
                 f.write_str(msg)
             },
+            RustersError::ConsumerNameTakenError => {
+                f.write_str("An active consumer already has that name")
+            },
+            RustersError::InactiveConsumerError => {
+                f.write_str("The consumer is deactivated")
+            },
+            RustersError::InsufficientScope(missing) => {
+                let msg = &format!("Missing scopes: {}", missing.join(", "));
+                f.write_str(msg)
+            },
             RustersError::InvalidCredentialsError => {
                 f.write_str("Invalid credentials")
             },
+            RustersError::InvalidMaxUsesError => {
+                f.write_str("A token must allow at least one use")
+            },
+            RustersError::InvalidSecondFactorError => {
+                f.write_str("Invalid second factor")
+            },
+            RustersError::InvalidTokenError => {
+                f.write_str("The token is invalid, expired or already used")
+            },
             RustersError::IOError(e) => {
                 let msg = &format!("{}", e);
                 f.write_str(msg)
             },
+            RustersError::JSONError(e) => {
+                let msg = &format!("{}", e);
+                f.write_str(msg)
+            },
+            RustersError::MasterKeyError => {
+                f.write_str("The master key is missing, malformed or does not match")
+            },
+            RustersError::MissingEmailError => {
+                f.write_str("No email address is set")
+            },
             RustersError::NotLoggedInError => {
                 f.write_str("Not logged in")
             },
+            RustersError::OAuthError(code) => {
+                let msg = &format!("OAuth request failed: {}", code.as_str());
+                f.write_str(msg)
+            },
             RustersError::SQLError(e) => {
                 let msg = &format!("{}", e);
                 f.write_str(msg)
             },
+            RustersError::TokenExhaustedError => {
+                f.write_str("The token has no uses left")
+            },
+            RustersError::TokenReuseError => {
+                f.write_str("A token was reused and has been revoked")
+            },
             RustersError::NoSessionError => {
                 f.write_str("The session is expired or does not exist")
             },
+            RustersError::SecondFactorRequiredError => {
+                f.write_str("A second factor is required to complete login")
+            },
+            RustersError::NoSigningKeyError => {
+                f.write_str("No usable signing key")
+            },
+            RustersError::WeakPassword(violations) => {
+                let msg = &format!(
+                    "Password {}",
+                    violations.iter()
+                        .map(|v| v.to_string())
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                );
+                f.write_str(msg)
+            },
         }
     }
 }
@@ -67,3 +151,11 @@ impl<T> MatchRustersError<T, std::io::Error> for Result<T, std::io::Error> {
         };
     }
 }
+impl<T> MatchRustersError<T, JSONError> for Result<T, JSONError> {
+    fn quick_match(self) -> Result<T, RustersError> {
+        return match self {
+            Ok(s) => Ok(s),
+            Err(e) => Err(RustersError::JSONError(e)),
+        };
+    }
+}
@@ -0,0 +1,78 @@
+pub mod signing_key;
+use {
+    crate::{
+        error::RustersError,
+        keyring::signing_key::{ KeyState, SigningKey, },
+    },
+    sqlx::SqlitePool,
+    std::collections::BTreeMap,
+};
+/// Secret keys by key id, one of which is active and used to sign. Previous
+/// keys stay in the ring so tokens signed before a rotation still verify until
+/// the key is removed
+#[derive(Clone, Default)]
+pub struct Keyring {
+    active: Option<String>,
+    keys: BTreeMap<String, Vec<u8>>,
+}
+impl std::fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Keyring")
+            .field("active", &self.active)
+            .field("keys", &self.keys.keys().collect::<Vec<&String>>())
+            .finish()
+    }
+}
+impl Keyring {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Builds a ring from the active and retiring `SigningKeys`, decrypting
+    /// them with `master`
+    pub async fn load(
+        db: &SqlitePool, master: &[u8; 32]
+    ) -> Result<Self, RustersError> {
+        let mut ring = Self::new();
+        for key in SigningKey::lookup_usable(db).await? {
+            let secret = key.secret(master)?;
+            match key.get_state() {
+                KeyState::Active => ring.rotate(key.get_kid(), secret),
+                _ => ring.insert(key.get_kid(), secret),
+            }
+        }
+        Ok(ring)
+    }
+    /// `load` with the master key from the environment
+    pub async fn load_from_env(db: &SqlitePool) -> Result<Self, RustersError> {
+        Self::load(db, &SigningKey::master_key_from_env()?).await
+    }
+    /// Adds a key used only for verification
+    pub fn insert(&mut self, kid: impl Into<String>, secret: impl Into<Vec<u8>>) {
+        self.keys.insert(kid.into(), secret.into());
+    }
+    /// Adds a key and signs with it from now on, keeping the previous key for
+    /// verification
+    pub fn rotate(&mut self, kid: impl Into<String>, secret: impl Into<Vec<u8>>) {
+        let kid = kid.into();
+        self.keys.insert(kid.clone(), secret.into());
+        self.active = Some(kid);
+    }
+    /// Drops a key, after which tokens signed with it no longer verify
+    pub fn remove<'a>(&mut self, kid: &'a str) {
+        self.keys.remove(kid);
+        if self.active.as_deref() == Some(kid) {
+            self.active = None;
+        }
+    }
+    pub fn get<'a>(&self, kid: &'a str) -> Option<&[u8]> {
+        self.keys.get(kid).map(|k| k.as_slice())
+    }
+    /// The id and secret of the key new tokens are signed with
+    pub fn active(&self) -> Option<(&str, &[u8])> {
+        let kid = self.active.as_deref()?;
+        self.get(kid).map(|secret| (kid, secret))
+    }
+    pub fn key_ids(&self) -> Vec<String> {
+        self.keys.keys().cloned().collect()
+    }
+}
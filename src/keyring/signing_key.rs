@@ -0,0 +1,289 @@
+use {
+    base64::URL_SAFE_NO_PAD,
+    chacha20poly1305::{
+        aead::{ Aead, KeyInit, Payload, },
+        ChaCha20Poly1305,
+        Nonce,
+    },
+    chrono::{
+        DateTime,
+        Duration,
+        Utc,
+    },
+    crate::error::{
+        MatchRustersError,
+        RustersError,
+    },
+    rand::RngCore,
+    sqlx::{ FromRow, SqlitePool, query, query_as, },
+};
+/// Where a `SigningKey` is in its lifecycle. The single `Active` key signs,
+/// `Retiring` keys only verify until their `retire_dt`, and `Retired` keys are
+/// kept for the record but never loaded into a `Keyring`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+pub enum KeyState {
+    Active,
+    Retiring,
+    Retired,
+}
+/// A secret key persisted in `SigningKeys`. The secret is encrypted with the
+/// master key from `MASTER_KEY_VAR` and bound to its key id, so a row copied
+/// under another id does not decrypt
+#[derive(FromRow)]
+pub struct SigningKey {
+    pk: i64,
+    kid: String,
+    secret: String,
+    state: KeyState,
+    activated_dt: Option<DateTime<Utc>>,
+    retire_dt: Option<DateTime<Utc>>,
+    retired_dt: Option<DateTime<Utc>>,
+    created_dt: DateTime<Utc>,
+}
+impl std::fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SigningKey")
+            .field("pk", &self.pk)
+            .field("kid", &self.kid)
+            .field("state", &self.state)
+            .field("activated_dt", &self.activated_dt)
+            .field("retire_dt", &self.retire_dt)
+            .field("retired_dt", &self.retired_dt)
+            .field("created_dt", &self.created_dt)
+            .finish()
+    }
+}
+impl SigningKey {
+    /// The environment variable holding the base64 encoded 32 byte master key
+    pub const MASTER_KEY_VAR: &'static str = "RUSTERS_MASTER_KEY";
+    const SECRET_LEN: usize = 32;
+    const NONCE_LEN: usize = 12;
+    pub fn get_pk(&self) -> i64 {
+        self.pk
+    }
+    pub fn get_kid(&self) -> String {
+        self.kid.clone()
+    }
+    pub fn get_state(&self) -> KeyState {
+        self.state
+    }
+    pub fn get_activated_dt(&self) -> Option<DateTime<Utc>> {
+        self.activated_dt
+    }
+    pub fn get_retire_dt(&self) -> Option<DateTime<Utc>> {
+        self.retire_dt
+    }
+    pub fn get_retired_dt(&self) -> Option<DateTime<Utc>> {
+        self.retired_dt
+    }
+    pub fn get_created_dt(&self) -> DateTime<Utc> {
+        self.created_dt
+    }
+    /// How long a key signs before `rotate_if_due` replaces it
+    pub fn default_rotate_every() -> Duration {
+        Duration::days(30)
+    }
+    /// How long a replaced key keeps verifying tokens it signed
+    pub fn default_overlap() -> Duration {
+        Duration::days(7)
+    }
+    /// Reads the master key from `MASTER_KEY_VAR`. Loading a `.env` file into
+    /// the environment is left to the binary
+    pub fn master_key_from_env() -> Result<[u8; 32], RustersError> {
+        let encoded = std::env::var(Self::MASTER_KEY_VAR)
+            .map_err(|_| RustersError::MasterKeyError)?;
+        base64::decode(encoded.trim())
+            .ok()
+            .and_then(|key| key.try_into().ok())
+            .ok_or(RustersError::MasterKeyError)
+    }
+    fn encrypt<'a>(
+        master: &[u8; 32], kid: &'a str, secret: &[u8]
+    ) -> Result<String, RustersError> {
+        let mut nonce = [0_u8; Self::NONCE_LEN];
+        rand::rngs::OsRng.fill_bytes(&mut nonce);
+        let sealed = ChaCha20Poly1305::new(master.into())
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload { msg: secret, aad: kid.as_bytes() },
+            )
+            .map_err(|_| RustersError::MasterKeyError)?;
+        Ok(base64::encode_config(
+            [nonce.as_slice(), &sealed].concat(), URL_SAFE_NO_PAD
+        ))
+    }
+    /// Decrypts the secret with `master`
+    pub fn secret(&self, master: &[u8; 32]) -> Result<Vec<u8>, RustersError> {
+        let sealed = base64::decode_config(&self.secret, URL_SAFE_NO_PAD)
+            .map_err(|_| RustersError::MasterKeyError)?;
+        if sealed.len() < Self::NONCE_LEN {
+            return Err(RustersError::MasterKeyError);
+        }
+        let (nonce, cipher) = sealed.split_at(Self::NONCE_LEN);
+        ChaCha20Poly1305::new(master.into())
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload { msg: cipher, aad: self.kid.as_bytes() },
+            )
+            .map_err(|_| RustersError::MasterKeyError)
+    }
+    pub async fn lookup_by_pk(
+        db: &SqlitePool, pk: i64
+    ) -> Result<Self, RustersError> {
+        query_as::<_, Self>("
+            select
+                pk,
+                kid,
+                secret,
+                state,
+                activated_dt,
+                retire_dt,
+                retired_dt,
+                created_dt
+            from SigningKeys
+            where pk = $1"
+        ).bind(pk)
+            .fetch_one(db)
+            .await
+            .quick_match()
+    }
+    /// The key with id `kid`, in any state
+    pub async fn lookup_by_kid<'a>(
+        db: &SqlitePool, kid: &'a str
+    ) -> Result<Self, RustersError> {
+        query_as::<_, Self>("
+            select
+                pk,
+                kid,
+                secret,
+                state,
+                activated_dt,
+                retire_dt,
+                retired_dt,
+                created_dt
+            from SigningKeys
+            where kid = $1"
+        ).bind(kid)
+            .fetch_one(db)
+            .await
+            .quick_match()
+    }
+    /// The active key and any retiring keys, which together verify tokens
+    pub async fn lookup_usable(db: &SqlitePool) -> Result<Vec<Self>, RustersError> {
+        query_as::<_, Self>("
+            select
+                pk,
+                kid,
+                secret,
+                state,
+                activated_dt,
+                retire_dt,
+                retired_dt,
+                created_dt
+            from SigningKeys
+            where state = $1
+            or (state = $2 and retire_dt > $3)
+            order by pk"
+        ).bind(KeyState::Active)
+            .bind(KeyState::Retiring)
+            .bind(Utc::now())
+            .fetch_all(db)
+            .await
+            .quick_match()
+    }
+    /// Generates a new active key, moving the current active key to retiring
+    /// for `overlap` so tokens it signed keep verifying
+    pub async fn rotate(
+        db: &SqlitePool, master: &[u8; 32], overlap: Option<Duration>
+    ) -> Result<Self, RustersError> {
+        let now = Utc::now();
+        let overlap = overlap.unwrap_or_else(Self::default_overlap);
+        let mut raw_kid = [0_u8; 16];
+        rand::rngs::OsRng.fill_bytes(&mut raw_kid);
+        let kid = base64::encode_config(raw_kid, URL_SAFE_NO_PAD);
+        let mut secret = [0_u8; Self::SECRET_LEN];
+        rand::rngs::OsRng.fill_bytes(&mut secret);
+        let sealed = Self::encrypt(master, &kid, &secret)?;
+        let mut tx = db.begin().await.quick_match()?;
+        query("
+            update SigningKeys
+            set state = $1,
+                retire_dt = $2
+            where state = $3"
+        ).bind(KeyState::Retiring)
+            .bind(now + overlap)
+            .bind(KeyState::Active)
+            .execute(&mut tx)
+            .await
+            .quick_match()?;
+        let pk = query("
+            insert into SigningKeys (
+                kid,
+                secret,
+                state,
+                activated_dt,
+                created_dt
+            ) values (
+                $1,
+                $2,
+                $3,
+                $4,
+                $5
+            )"
+        ).bind(&kid)
+            .bind(sealed)
+            .bind(KeyState::Active)
+            .bind(now)
+            .bind(now)
+            .execute(&mut tx)
+            .await
+            .quick_match()?
+            .last_insert_rowid();
+        tx.commit().await.quick_match()?;
+        Self::lookup_by_pk(db, pk).await
+    }
+    /// Moves retiring keys whose overlap has passed to retired
+    pub async fn retire_expired(db: &SqlitePool) -> Result<(), RustersError> {
+        let now = Utc::now();
+        query("
+            update SigningKeys
+            set state = $1,
+                retired_dt = $2
+            where state = $3
+            and retire_dt <= $2"
+        ).bind(KeyState::Retired)
+            .bind(now)
+            .bind(KeyState::Retiring)
+            .execute(db)
+            .await
+            .quick_match()?;
+        Ok(())
+    }
+    /// Retires expired keys and rotates when there is no active key or the
+    /// active key has signed for `rotate_every`. Meant to be called on a
+    /// schedule; returns the new key when a rotation happened
+    pub async fn rotate_if_due(
+        db: &SqlitePool,
+        master: &[u8; 32],
+        rotate_every: Option<Duration>,
+        overlap: Option<Duration>,
+    ) -> Result<Option<Self>, RustersError> {
+        Self::retire_expired(db).await?;
+        let rotate_every = rotate_every.unwrap_or_else(Self::default_rotate_every);
+        let due = query_as::<_, (i64,)>("
+            select count(*)
+            from SigningKeys
+            where state = $1
+            and activated_dt > $2"
+        ).bind(KeyState::Active)
+            .bind(Utc::now() - rotate_every)
+            .fetch_one(db)
+            .await
+            .quick_match()?.0 == 0;
+        if !due {
+            return Ok(None);
+        }
+        Self::rotate(db, master, overlap).await.map(Some)
+    }
+}
@@ -1,7 +1,15 @@
+#![allow(
+    clippy::extra_unused_lifetimes,
+    clippy::needless_lifetimes,
+    clippy::needless_return,
+)]
 #[cfg(test)]
 mod tests;
 mod error;
+mod keyring;
 mod migrator;
+#[cfg(feature = "server")]
+mod server;
 mod session;
 mod token;
 mod user;
@@ -12,17 +20,78 @@ pub use {
         RustersError,
     },
     hash::{ Basic, Hash, Secure, },
+    keyring::{
+        Keyring,
+        signing_key::{ KeyState, SigningKey, },
+    },
     migrator::RustersMigrator,
     session::{
         cookie::SessionCookie,
+        remember_me::RememberMe,
         Session,
     },
     token::{
         Token,
         consumable_token::{
             ConsumableToken,
+            consumable_token_use::ConsumableTokenUse,
             consumer::Consumer,
         },
+        introspection::Introspection,
+        oauth::{
+            AuthorizationCode,
+            AuthorizationRequest,
+            AuthorizationResponse,
+            ErrorResponse,
+            OAuthErrorCode,
+            OAuthServer,
+            OAuthToken,
+            TokenRequest,
+            TokenResponse,
+        },
+        refresh_token::RefreshToken,
+        signed_token::SignedToken,
+    },
+    user::{
+        api_key::ApiKey,
+        email_change::{
+            EmailChange,
+            EmailChangeHook,
+        },
+        email_verification::EmailVerification,
+        login_attempt::{
+            LoginAttempt,
+            LoginPolicy,
+        },
+        magic_link::MagicLink,
+        password_history::PasswordHistory,
+        password_policy::{
+            breached_passwords::BreachedPasswords,
+            PasswordCheck,
+            PasswordPolicy,
+            PolicyViolation,
+        },
+        recovery_code::RecoveryCode,
+        totp::Totp,
+        User,
+    },
+};
+#[cfg(feature = "jwt")]
+pub use token::{
+    jwt::{
+        Jwt,
+        JwtConfig,
+        JwtKey,
     },
-    user::User,
+    oauth::oidc::{
+        OidcProvider,
+        UserInfo,
+    },
+};
+#[cfg(feature = "paseto")]
+pub use token::paseto::{
+    Paseto,
+    PasetoKey,
 };
+#[cfg(feature = "server")]
+pub use server::RustersServer;
@@ -14,6 +14,31 @@ impl RustersMigrator {
         Self::tbl_sessioncookies(db).await?;
         Self::tbl_consumers(db).await?;
         Self::tbl_consumable_tokens(db).await?;
+        Self::tbl_login_attempts(db).await?;
+        Self::tbl_password_history(db).await?;
+        Self::tbl_totps(db).await?;
+        Self::tbl_recovery_codes(db).await?;
+        Self::col_users_email(db).await?;
+        Self::tbl_email_verifications(db).await?;
+        Self::tbl_email_changes(db).await?;
+        Self::tbl_magic_links(db).await?;
+        Self::tbl_remember_me_tokens(db).await?;
+        Self::tbl_refresh_token_families(db).await?;
+        Self::tbl_refresh_tokens(db).await?;
+        Self::tbl_token_scopes(db).await?;
+        Self::tbl_api_keys(db).await?;
+        Self::tbl_signing_keys(db).await?;
+        Self::col_consumers_secret_hash(db).await?;
+        Self::tbl_consumer_redirect_uris(db).await?;
+        Self::tbl_authorization_codes(db).await?;
+        Self::tbl_oauth_tokens(db).await?;
+        Self::col_authorization_codes_nonce(db).await?;
+        Self::col_consumers_previous_secret(db).await?;
+        Self::col_consumable_tokens_payload(db).await?;
+        Self::col_consumable_tokens_uses(db).await?;
+        Self::tbl_consumable_token_uses(db).await?;
+        Self::tbl_consumer_scopes(db).await?;
+        Self::col_authorization_codes_redirect_uri_explicit(db).await?;
         Ok(())
     }
     async fn tbl_users(db: &SqlitePool) -> Result<(), RustersError> {
@@ -175,4 +200,666 @@ impl RustersMigrator {
         }
         Ok(())
     }
+    async fn tbl_login_attempts(db: &SqlitePool) -> Result<(), RustersError> {
+        let exists = query_as::<_, (i64,)>("
+            select count(*)
+            from sqlite_master
+            where Name = 'LoginAttempts'
+            and type = 'table';"
+        ).fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if !exists {
+            query("
+                create table LoginAttempts (
+                    pk integer primary key autoincrement,
+                    username text not null,
+                    client_key text null,
+                    is_active integer not null default 1,
+                    locked_until_dt text null,
+                    created_dt text not null
+                );
+                create index LoginAttemptsUsername
+                on LoginAttempts (username)
+                where is_active = 1;"
+            ).execute(db)
+                .await
+                .quick_match()?;
+        }
+        Ok(())
+    }
+    async fn tbl_password_history(db: &SqlitePool) -> Result<(), RustersError> {
+        let exists = query_as::<_, (i64,)>("
+            select count(*)
+            from sqlite_master
+            where Name = 'PasswordHistory'
+            and type = 'table';"
+        ).fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if !exists {
+            query("
+                create table PasswordHistory (
+                    pk integer primary key autoincrement,
+                    user_pk integer not null,
+                    password_hash text not null,
+                    salt text not null,
+                    created_dt text not null,
+                    foreign key (user_pk) references Users (pk)
+                );
+                create index PasswordHistoryUser
+                on PasswordHistory (user_pk);"
+            ).execute(db)
+                .await
+                .quick_match()?;
+        }
+        Ok(())
+    }
+    async fn tbl_totps(db: &SqlitePool) -> Result<(), RustersError> {
+        let exists = query_as::<_, (i64,)>("
+            select count(*)
+            from sqlite_master
+            where Name = 'Totps'
+            and type = 'table';"
+        ).fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if !exists {
+            query("
+                create table Totps (
+                    pk integer primary key autoincrement,
+                    user_pk integer not null,
+                    secret text not null,
+                    is_confirmed integer not null default 0,
+                    last_step integer null,
+                    is_active integer not null default 1,
+                    created_dt text not null,
+                    foreign key (user_pk) references Users (pk)
+                );
+                create index TotpsUser
+                on Totps (user_pk)
+                where is_active = 1;"
+            ).execute(db)
+                .await
+                .quick_match()?;
+        }
+        Ok(())
+    }
+    async fn tbl_recovery_codes(db: &SqlitePool) -> Result<(), RustersError> {
+        let exists = query_as::<_, (i64,)>("
+            select count(*)
+            from sqlite_master
+            where Name = 'RecoveryCodes'
+            and type = 'table';"
+        ).fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if !exists {
+            query("
+                create table RecoveryCodes (
+                    pk integer primary key autoincrement,
+                    user_pk integer not null,
+                    token_pk integer not null,
+                    selector text not null,
+                    created_dt text not null,
+                    foreign key (user_pk) references Users (pk),
+                    foreign key (token_pk) references Tokens (pk)
+                );
+                create index RecoveryCodesUser
+                on RecoveryCodes (user_pk);"
+            ).execute(db)
+                .await
+                .quick_match()?;
+        }
+        Ok(())
+    }
+    async fn col_users_email(db: &SqlitePool) -> Result<(), RustersError> {
+        let email_exists = query_as::<_, (i64,)>("
+            select count(*)
+            from pragma_table_info('Users')
+            where name = 'email';"
+        ).fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if !email_exists {
+            query("
+                alter table Users
+                add column email text null;"
+            ).execute(db)
+                .await
+                .quick_match()?;
+        }
+        let verified_dt_exists = query_as::<_, (i64,)>("
+            select count(*)
+            from pragma_table_info('Users')
+            where name = 'email_verified_dt';"
+        ).fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if !verified_dt_exists {
+            query("
+                alter table Users
+                add column email_verified_dt text null;"
+            ).execute(db)
+                .await
+                .quick_match()?;
+        }
+        Ok(())
+    }
+    async fn tbl_email_verifications(db: &SqlitePool) -> Result<(), RustersError> {
+        let exists = query_as::<_, (i64,)>("
+            select count(*)
+            from sqlite_master
+            where Name = 'EmailVerifications'
+            and type = 'table';"
+        ).fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if !exists {
+            query("
+                create table EmailVerifications (
+                    pk integer primary key autoincrement,
+                    user_pk integer not null,
+                    consumable_token_pk integer not null,
+                    email text not null,
+                    created_dt text not null,
+                    foreign key (user_pk) references Users (pk),
+                    foreign key (consumable_token_pk) references ConsumableTokens (pk)
+                );"
+            ).execute(db)
+                .await
+                .quick_match()?;
+        }
+        Ok(())
+    }
+    async fn tbl_email_changes(db: &SqlitePool) -> Result<(), RustersError> {
+        let exists = query_as::<_, (i64,)>("
+            select count(*)
+            from sqlite_master
+            where Name = 'EmailChanges'
+            and type = 'table';"
+        ).fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if !exists {
+            query("
+                create table EmailChanges (
+                    pk integer primary key autoincrement,
+                    user_pk integer not null,
+                    consumable_token_pk integer not null,
+                    old_email text null,
+                    new_email text not null,
+                    is_active integer not null default 1,
+                    created_dt text not null,
+                    foreign key (user_pk) references Users (pk),
+                    foreign key (consumable_token_pk) references ConsumableTokens (pk)
+                );"
+            ).execute(db)
+                .await
+                .quick_match()?;
+        }
+        Ok(())
+    }
+    async fn tbl_magic_links(db: &SqlitePool) -> Result<(), RustersError> {
+        let exists = query_as::<_, (i64,)>("
+            select count(*)
+            from sqlite_master
+            where Name = 'MagicLinks'
+            and type = 'table';"
+        ).fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if !exists {
+            query("
+                create table MagicLinks (
+                    pk integer primary key autoincrement,
+                    user_pk integer not null,
+                    consumable_token_pk integer not null,
+                    session_pk integer null,
+                    created_dt text not null,
+                    foreign key (user_pk) references Users (pk),
+                    foreign key (consumable_token_pk) references ConsumableTokens (pk),
+                    foreign key (session_pk) references Sessions (pk)
+                );"
+            ).execute(db)
+                .await
+                .quick_match()?;
+        }
+        Ok(())
+    }
+    async fn tbl_remember_me_tokens(db: &SqlitePool) -> Result<(), RustersError> {
+        let exists = query_as::<_, (i64,)>("
+            select count(*)
+            from sqlite_master
+            where Name = 'RememberMeTokens'
+            and type = 'table';"
+        ).fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if !exists {
+            query("
+                create table RememberMeTokens (
+                    pk integer primary key autoincrement,
+                    user_pk integer not null,
+                    selector text not null unique,
+                    validator_hash text not null,
+                    is_active integer not null default 1,
+                    last_used_dt text null,
+                    expired_dt text not null,
+                    created_dt text not null,
+                    foreign key (user_pk) references Users (pk)
+                );"
+            ).execute(db)
+                .await
+                .quick_match()?;
+        }
+        Ok(())
+    }
+    async fn tbl_refresh_token_families(db: &SqlitePool) -> Result<(), RustersError> {
+        let exists = query_as::<_, (i64,)>("
+            select count(*)
+            from sqlite_master
+            where Name = 'RefreshTokenFamilies'
+            and type = 'table';"
+        ).fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if !exists {
+            query("
+                create table RefreshTokenFamilies (
+                    pk integer primary key autoincrement,
+                    user_pk integer not null,
+                    is_active integer not null default 1,
+                    created_dt text not null,
+                    foreign key (user_pk) references Users (pk)
+                );"
+            ).execute(db)
+                .await
+                .quick_match()?;
+        }
+        Ok(())
+    }
+    async fn tbl_refresh_tokens(db: &SqlitePool) -> Result<(), RustersError> {
+        let exists = query_as::<_, (i64,)>("
+            select count(*)
+            from sqlite_master
+            where Name = 'RefreshTokens'
+            and type = 'table';"
+        ).fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if !exists {
+            query("
+                create table RefreshTokens (
+                    pk integer primary key autoincrement,
+                    family_pk integer not null,
+                    token_pk integer not null,
+                    access_token_pk integer not null,
+                    is_retired integer not null default 0,
+                    created_dt text not null,
+                    foreign key (family_pk) references RefreshTokenFamilies (pk),
+                    foreign key (token_pk) references Tokens (pk),
+                    foreign key (access_token_pk) references Tokens (pk)
+                );"
+            ).execute(db)
+                .await
+                .quick_match()?;
+        }
+        Ok(())
+    }
+    async fn tbl_token_scopes(db: &SqlitePool) -> Result<(), RustersError> {
+        let exists = query_as::<_, (i64,)>("
+            select count(*)
+            from sqlite_master
+            where Name = 'TokenScopes'
+            and type = 'table';"
+        ).fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if !exists {
+            query("
+                create table TokenScopes (
+                    pk integer primary key autoincrement,
+                    token_pk integer not null,
+                    scope text not null,
+                    created_dt text not null,
+                    foreign key (token_pk) references Tokens (pk)
+                );
+                create unique index TokenScopesUniqueScope
+                on TokenScopes (
+                    token_pk,
+                    scope
+                );"
+            ).execute(db)
+                .await
+                .quick_match()?;
+        }
+        Ok(())
+    }
+    async fn tbl_api_keys(db: &SqlitePool) -> Result<(), RustersError> {
+        let exists = query_as::<_, (i64,)>("
+            select count(*)
+            from sqlite_master
+            where Name = 'ApiKeys'
+            and type = 'table';"
+        ).fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if !exists {
+            query("
+                create table ApiKeys (
+                    pk integer primary key autoincrement,
+                    user_pk integer not null,
+                    token_pk integer not null,
+                    name text not null,
+                    prefix text not null unique,
+                    last_used_dt text null,
+                    is_active integer not null default 1,
+                    created_dt text not null,
+                    foreign key (user_pk) references Users (pk),
+                    foreign key (token_pk) references Tokens (pk)
+                );"
+            ).execute(db)
+                .await
+                .quick_match()?;
+        }
+        Ok(())
+    }
+    async fn tbl_signing_keys(db: &SqlitePool) -> Result<(), RustersError> {
+        let exists = query_as::<_, (i64,)>("
+            select count(*)
+            from sqlite_master
+            where Name = 'SigningKeys'
+            and type = 'table';"
+        ).fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if !exists {
+            query("
+                create table SigningKeys (
+                    pk integer primary key autoincrement,
+                    kid text not null unique,
+                    secret text not null,
+                    state text not null,
+                    activated_dt text null,
+                    retire_dt text null,
+                    retired_dt text null,
+                    created_dt text not null
+                );"
+            ).execute(db)
+                .await
+                .quick_match()?;
+        }
+        Ok(())
+    }
+    async fn col_consumers_secret_hash(db: &SqlitePool) -> Result<(), RustersError> {
+        let exists = query_as::<_, (i64,)>("
+            select count(*)
+            from pragma_table_info('Consumers')
+            where name = 'secret_hash';"
+        ).fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if !exists {
+            query("
+                alter table Consumers
+                add column secret_hash text null;"
+            ).execute(db)
+                .await
+                .quick_match()?;
+        }
+        Ok(())
+    }
+    async fn tbl_consumer_redirect_uris(db: &SqlitePool) -> Result<(), RustersError> {
+        let exists = query_as::<_, (i64,)>("
+            select count(*)
+            from sqlite_master
+            where Name = 'ConsumerRedirectUris'
+            and type = 'table';"
+        ).fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if !exists {
+            query("
+                create table ConsumerRedirectUris (
+                    pk integer primary key autoincrement,
+                    consumer_pk integer not null,
+                    redirect_uri text not null,
+                    created_dt text not null,
+                    unique (consumer_pk, redirect_uri),
+                    foreign key (consumer_pk) references Consumers (pk)
+                );"
+            ).execute(db)
+                .await
+                .quick_match()?;
+        }
+        Ok(())
+    }
+    async fn tbl_authorization_codes(db: &SqlitePool) -> Result<(), RustersError> {
+        let exists = query_as::<_, (i64,)>("
+            select count(*)
+            from sqlite_master
+            where Name = 'AuthorizationCodes'
+            and type = 'table';"
+        ).fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if !exists {
+            query("
+                create table AuthorizationCodes (
+                    pk integer primary key autoincrement,
+                    consumable_token_pk integer not null,
+                    user_pk integer not null,
+                    redirect_uri text not null,
+                    code_challenge text not null,
+                    created_dt text not null,
+                    foreign key (consumable_token_pk) references ConsumableTokens (pk),
+                    foreign key (user_pk) references Users (pk)
+                );"
+            ).execute(db)
+                .await
+                .quick_match()?;
+        }
+        Ok(())
+    }
+    async fn tbl_oauth_tokens(db: &SqlitePool) -> Result<(), RustersError> {
+        let exists = query_as::<_, (i64,)>("
+            select count(*)
+            from sqlite_master
+            where Name = 'OAuthTokens'
+            and type = 'table';"
+        ).fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if !exists {
+            query("
+                create table OAuthTokens (
+                    pk integer primary key autoincrement,
+                    token_pk integer not null unique,
+                    consumer_pk integer not null,
+                    user_pk integer null,
+                    is_refresh integer not null default 0,
+                    created_dt text not null,
+                    foreign key (token_pk) references Tokens (pk),
+                    foreign key (consumer_pk) references Consumers (pk),
+                    foreign key (user_pk) references Users (pk)
+                );"
+            ).execute(db)
+                .await
+                .quick_match()?;
+        }
+        Ok(())
+    }
+    async fn col_authorization_codes_nonce(db: &SqlitePool) -> Result<(), RustersError> {
+        let exists = query_as::<_, (i64,)>("
+            select count(*)
+            from pragma_table_info('AuthorizationCodes')
+            where name = 'nonce';"
+        ).fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if !exists {
+            query("
+                alter table AuthorizationCodes
+                add column nonce text null;"
+            ).execute(db)
+                .await
+                .quick_match()?;
+        }
+        Ok(())
+    }
+    async fn col_consumers_previous_secret(db: &SqlitePool) -> Result<(), RustersError> {
+        let hash_exists = query_as::<_, (i64,)>("
+            select count(*)
+            from pragma_table_info('Consumers')
+            where name = 'previous_secret_hash';"
+        ).fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if !hash_exists {
+            query("
+                alter table Consumers
+                add column previous_secret_hash text null;"
+            ).execute(db)
+                .await
+                .quick_match()?;
+        }
+        let expired_dt_exists = query_as::<_, (i64,)>("
+            select count(*)
+            from pragma_table_info('Consumers')
+            where name = 'previous_secret_expired_dt';"
+        ).fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if !expired_dt_exists {
+            query("
+                alter table Consumers
+                add column previous_secret_expired_dt text null;"
+            ).execute(db)
+                .await
+                .quick_match()?;
+        }
+        Ok(())
+    }
+    async fn col_consumable_tokens_payload(db: &SqlitePool) -> Result<(), RustersError> {
+        let exists = query_as::<_, (i64,)>("
+            select count(*)
+            from pragma_table_info('ConsumableTokens')
+            where name = 'payload';"
+        ).fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if !exists {
+            query("
+                alter table ConsumableTokens
+                add column payload text null;"
+            ).execute(db)
+                .await
+                .quick_match()?;
+        }
+        Ok(())
+    }
+    async fn col_consumable_tokens_uses(db: &SqlitePool) -> Result<(), RustersError> {
+        let max_uses_exists = query_as::<_, (i64,)>("
+            select count(*)
+            from pragma_table_info('ConsumableTokens')
+            where name = 'max_uses';"
+        ).fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if !max_uses_exists {
+            query("
+                alter table ConsumableTokens
+                add column max_uses integer not null default 1;"
+            ).execute(db)
+                .await
+                .quick_match()?;
+        }
+        let uses_exists = query_as::<_, (i64,)>("
+            select count(*)
+            from pragma_table_info('ConsumableTokens')
+            where name = 'uses';"
+        ).fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if !uses_exists {
+            query("
+                alter table ConsumableTokens
+                add column uses integer not null default 0;"
+            ).execute(db)
+                .await
+                .quick_match()?;
+        }
+        Ok(())
+    }
+    async fn tbl_consumable_token_uses(db: &SqlitePool) -> Result<(), RustersError> {
+        let exists = query_as::<_, (i64,)>("
+            select count(*)
+            from sqlite_master
+            where Name = 'ConsumableTokenUses'
+            and type = 'table';"
+        ).fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if !exists {
+            query("
+                create table ConsumableTokenUses (
+                    pk integer primary key autoincrement,
+                    consumable_token_pk integer not null,
+                    user_pk integer null,
+                    used_dt text not null,
+                    foreign key (consumable_token_pk) references ConsumableTokens (pk),
+                    foreign key (user_pk) references Users (pk)
+                );"
+            ).execute(db)
+                .await
+                .quick_match()?;
+        }
+        Ok(())
+    }
+    async fn tbl_consumer_scopes(db: &SqlitePool) -> Result<(), RustersError> {
+        let exists = query_as::<_, (i64,)>("
+            select count(*)
+            from sqlite_master
+            where Name = 'ConsumerScopes'
+            and type = 'table';"
+        ).fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if !exists {
+            query("
+                create table ConsumerScopes (
+                    pk integer primary key autoincrement,
+                    consumer_pk integer not null,
+                    scope text not null,
+                    created_dt text not null,
+                    unique (consumer_pk, scope),
+                    foreign key (consumer_pk) references Consumers (pk)
+                );"
+            ).execute(db)
+                .await
+                .quick_match()?;
+        }
+        Ok(())
+    }
+    async fn col_authorization_codes_redirect_uri_explicit(
+        db: &SqlitePool
+    ) -> Result<(), RustersError> {
+        let exists = query_as::<_, (i64,)>("
+            select count(*)
+            from pragma_table_info('AuthorizationCodes')
+            where name = 'is_redirect_uri_explicit';"
+        ).fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if !exists {
+            query("
+                alter table AuthorizationCodes
+                add column is_redirect_uri_explicit integer not null default 0;"
+            ).execute(db)
+                .await
+                .quick_match()?;
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,89 @@
+use {
+    crate::{
+        error::RustersError,
+        token::{
+            consumable_token::consumer::Consumer,
+            oauth::ErrorResponse,
+            Token,
+        },
+    },
+    serde::Deserialize,
+    sqlx::SqlitePool,
+    tide::{
+        http::auth::BasicAuth,
+        Body,
+        Request,
+        Response,
+        StatusCode,
+    },
+};
+/// The form posted to the introspection and revocation endpoints. Clients may
+/// authenticate with HTTP basic auth instead of `client_id` and
+/// `client_secret`
+#[derive(Deserialize)]
+struct TokenForm {
+    token: String,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+}
+/// HTTP handlers for RFC 7662 introspection and RFC 7009 revocation, served
+/// over `SqlitePool` state
+pub struct RustersServer;
+impl RustersServer {
+    /// A server with `introspect` at `/introspect` and `revoke` at `/revoke`
+    pub fn app(db: SqlitePool) -> tide::Server<SqlitePool> {
+        let mut app = tide::with_state(db);
+        app.at("/introspect").post(Self::introspect);
+        app.at("/revoke").post(Self::revoke);
+        app
+    }
+    fn error(status: StatusCode, error: &RustersError) -> tide::Result {
+        let mut response = Response::new(status);
+        response.set_body(Body::from_json(&ErrorResponse::from(error))?);
+        Ok(response)
+    }
+    async fn authenticate(
+        req: &Request<SqlitePool>, form: &TokenForm
+    ) -> Result<Consumer, RustersError> {
+        let (client_id, client_secret) = match BasicAuth::from_headers(req) {
+            Ok(Some(auth)) => (
+                Some(auth.username().to_string()),
+                Some(auth.password().to_string()),
+            ),
+            _ => (form.client_id.clone(), form.client_secret.clone()),
+        };
+        let client_id = client_id.ok_or(RustersError::InvalidCredentialsError)?;
+        Consumer::authenticate_client(
+            req.state(), &client_id, client_secret.as_deref()
+        ).await
+    }
+    /// Introspects the posted token for a confidential client
+    pub async fn introspect(mut req: Request<SqlitePool>) -> tide::Result {
+        let form: TokenForm = req.body_form().await?;
+        match Self::authenticate(&req, &form).await {
+            Ok(consumer) if consumer.get_is_confidential() => {},
+            _ => return Self::error(
+                StatusCode::Unauthorized, &RustersError::InvalidCredentialsError
+            ),
+        }
+        match Token::introspect(req.state(), &form.token).await {
+            Ok(introspection) => Ok(Body::from_json(&introspection)?.into()),
+            Err(e) => Self::error(StatusCode::InternalServerError, &e),
+        }
+    }
+    /// Revokes the posted token on behalf of the client it was issued to
+    pub async fn revoke(mut req: Request<SqlitePool>) -> tide::Result {
+        let form: TokenForm = req.body_form().await?;
+        let consumer = match Self::authenticate(&req, &form).await {
+            Ok(consumer) => consumer,
+            Err(e) => return Self::error(StatusCode::Unauthorized, &e),
+        };
+        match Token::revoke(req.state(), &consumer, &form.token).await {
+            Ok(()) => Ok(Response::new(StatusCode::Ok)),
+            Err(RustersError::SQLError(e)) => Self::error(
+                StatusCode::InternalServerError, &RustersError::SQLError(e)
+            ),
+            Err(e) => Self::error(StatusCode::BadRequest, &e),
+        }
+    }
+}
@@ -1,4 +1,5 @@
 pub mod cookie;
+pub mod remember_me;
 use {
     chrono::{
         DateTime,
@@ -9,7 +9,15 @@ use {
             RustersError,
         },
         session::Session,
-        user::User,
+        user::{
+            login_attempt::{
+                LoginAttempt,
+                LoginPolicy,
+            },
+            recovery_code::RecoveryCode,
+            totp::Totp,
+            User,
+        },
     },
     sqlx::{ FromRow, SqlitePool, query, query_as, },
 };
@@ -150,14 +158,141 @@ impl SessionCookie {
     ) -> Result<bool, RustersError> {
         Ok(Self::read(db, session, Self::LOGIN_COOKIE).await?.is_some())
     }
+    /// Holds the username of a login waiting on its second factor
+    pub const SECOND_FACTOR_COOKIE: &'static str = "SECOND_FACTOR";
+    /// Logs `user` in to the session. Users with two-factor enabled are only
+    /// marked as pending and `SecondFactorRequiredError` is returned until
+    /// `complete_second_factor` succeeds
     pub async fn login(
         db: &SqlitePool, session: &Session, user: &User
     ) -> Result<Self, RustersError> {
+        if Totp::is_enabled(db, user).await? {
+            Self::set(
+                db, session, Self::SECOND_FACTOR_COOKIE, &user.get_username()
+            ).await?;
+            return Err(RustersError::SecondFactorRequiredError);
+        }
+        Self::set(db, session, Self::LOGIN_COOKIE, &user.get_username()).await
+    }
+    pub async fn has_second_factor_cookie(
+        db: &SqlitePool, session: &Session
+    ) -> Result<bool, RustersError> {
+        Ok(Self::read(db, session, Self::SECOND_FACTOR_COOKIE).await?.is_some())
+    }
+    async fn pending_second_factor(
+        db: &SqlitePool, session: &Session
+    ) -> Result<User, RustersError> {
+        let pending = match Self::read(db, session, Self::SECOND_FACTOR_COOKIE).await? {
+            Some(pending) => pending,
+            None => return Err(RustersError::NotLoggedInError),
+        };
+        User::lookup(db, &pending.value).await
+    }
+    /// Counts a failed second factor against the pending user under `policy`.
+    /// Once the failures lock the account the pending login is dropped, so
+    /// the password has to be presented again after the lockout
+    async fn fail_second_factor<'a>(
+        db: &SqlitePool,
+        session: &Session,
+        user: &User,
+        client_key: Option<&'a str>,
+        policy: &LoginPolicy,
+    ) -> Result<Self, RustersError> {
+        let locked = LoginAttempt::fail(
+            db, &user.get_username(), client_key, policy
+        ).await?;
+        match locked {
+            Some(until) => {
+                Self::delete(db, session, Self::SECOND_FACTOR_COOKIE).await?;
+                Err(RustersError::AccountLocked { until })
+            },
+            None => Err(RustersError::InvalidSecondFactorError),
+        }
+    }
+    /// Finishes a login left pending by `login` once `code` passes
+    pub async fn complete_second_factor<'a>(
+        db: &SqlitePool, session: &Session, code: &'a str, skew: Option<i64>
+    ) -> Result<Self, RustersError> {
+        Self::complete_second_factor_with_policy(
+            db, session, code, skew, None, &LoginPolicy::default()
+        ).await
+    }
+    /// `complete_second_factor` with wrong codes counted as failed logins
+    /// under `policy`, and refused at all while the account is locked
+    pub async fn complete_second_factor_with_policy<'a>(
+        db: &SqlitePool,
+        session: &Session,
+        code: &'a str,
+        skew: Option<i64>,
+        client_key: Option<&'a str>,
+        policy: &LoginPolicy,
+    ) -> Result<Self, RustersError> {
+        let user = Self::pending_second_factor(db, session).await?;
+        let locked = LoginAttempt::locked_until(
+            db, &user.get_username(), client_key, policy
+        ).await?;
+        if let Some(until) = locked {
+            return Err(RustersError::AccountLocked { until });
+        }
+        let mut totp = match Totp::lookup(db, &user).await? {
+            Some(totp) => totp,
+            None => return Err(RustersError::InvalidSecondFactorError),
+        };
+        match totp.verify(db, code, skew).await {
+            Ok(()) => {},
+            Err(RustersError::InvalidSecondFactorError) => {
+                return Self::fail_second_factor(
+                    db, session, &user, client_key, policy
+                ).await;
+            },
+            Err(e) => return Err(e),
+        }
+        LoginAttempt::succeed(db, &user.get_username(), client_key, policy).await?;
+        Self::delete(db, session, Self::SECOND_FACTOR_COOKIE).await?;
+        Self::set(db, session, Self::LOGIN_COOKIE, &user.get_username()).await
+    }
+    /// Finishes a login left pending by `login` by spending one of the
+    /// user's recovery codes in place of a one-time password
+    pub async fn complete_with_recovery_code<'a>(
+        db: &SqlitePool, session: &Session, code: &'a str
+    ) -> Result<Self, RustersError> {
+        Self::complete_with_recovery_code_with_policy(
+            db, session, code, None, &LoginPolicy::default()
+        ).await
+    }
+    /// `complete_with_recovery_code` with wrong codes counted as failed logins
+    /// under `policy`, like wrong one-time passwords
+    pub async fn complete_with_recovery_code_with_policy<'a>(
+        db: &SqlitePool,
+        session: &Session,
+        code: &'a str,
+        client_key: Option<&'a str>,
+        policy: &LoginPolicy,
+    ) -> Result<Self, RustersError> {
+        let user = Self::pending_second_factor(db, session).await?;
+        let locked = LoginAttempt::locked_until(
+            db, &user.get_username(), client_key, policy
+        ).await?;
+        if let Some(until) = locked {
+            return Err(RustersError::AccountLocked { until });
+        }
+        match RecoveryCode::redeem(db, &user, code).await {
+            Ok(()) => {},
+            Err(RustersError::InvalidSecondFactorError) => {
+                return Self::fail_second_factor(
+                    db, session, &user, client_key, policy
+                ).await;
+            },
+            Err(e) => return Err(e),
+        }
+        LoginAttempt::succeed(db, &user.get_username(), client_key, policy).await?;
+        Self::delete(db, session, Self::SECOND_FACTOR_COOKIE).await?;
         Self::set(db, session, Self::LOGIN_COOKIE, &user.get_username()).await
     }
     pub async fn logout(
         db: &SqlitePool, session: &Session
     ) -> Result<(), RustersError> {
+        Self::delete(db, session, Self::SECOND_FACTOR_COOKIE).await?;
         Self::delete(db, session, Self::LOGIN_COOKIE).await
     }
 }
@@ -0,0 +1,232 @@
+use {
+    chrono::{
+        DateTime,
+        Duration,
+        Utc,
+    },
+    crate::{
+        error::{
+            MatchRustersError,
+            RustersError,
+        },
+        hash::{
+            Basic,
+            Hash,
+            Secure,
+        },
+        session::{
+            cookie::SessionCookie,
+            Session,
+        },
+        token::Token,
+        user::User,
+    },
+    sqlx::{ FromRow, SqlitePool, query, query_as, },
+};
+/// A long lived token which logs a `User` back in once their `Session` has
+/// expired. It is presented as `selector:validator`, the selector finding the
+/// row and the validator, stored as a `Secure` hash, proving possession. The
+/// validator changes on every use, so an old validator showing up again means
+/// the token was copied and every remember-me token of the user is revoked
+#[derive(FromRow)]
+pub struct RememberMe {
+    pk: i64,
+    user_pk: i64,
+    selector: String,
+    validator_hash: String,
+    is_active: bool,
+    last_used_dt: Option<DateTime<Utc>>,
+    expired_dt: DateTime<Utc>,
+    created_dt: DateTime<Utc>,
+}
+impl RememberMe {
+    pub fn get_pk(&self) -> i64 {
+        self.pk
+    }
+    pub fn get_user_pk(&self) -> i64 {
+        self.user_pk
+    }
+    pub fn get_selector(&self) -> String {
+        self.selector.clone()
+    }
+    pub fn get_validator_hash(&self) -> String {
+        self.validator_hash.clone()
+    }
+    pub fn get_is_active(&self) -> bool {
+        self.is_active
+    }
+    pub fn get_last_used_dt(&self) -> Option<DateTime<Utc>> {
+        self.last_used_dt
+    }
+    pub fn get_expired_dt(&self) -> DateTime<Utc> {
+        self.expired_dt
+    }
+    pub fn get_created_dt(&self) -> DateTime<Utc> {
+        self.created_dt
+    }
+    pub fn default_expires() -> Duration {
+        Duration::days(30)
+    }
+    pub async fn lookup_by_pk(
+        db: &SqlitePool, pk: i64
+    ) -> Result<Self, RustersError> {
+        query_as::<_, Self>("
+            select
+                pk,
+                user_pk,
+                selector,
+                validator_hash,
+                is_active,
+                last_used_dt,
+                expired_dt,
+                created_dt
+            from RememberMeTokens
+            where pk = $1"
+        ).bind(pk)
+            .fetch_one(db)
+            .await
+            .quick_match()
+    }
+    pub async fn lookup<'a>(
+        db: &SqlitePool, selector: &'a str
+    ) -> Result<Self, RustersError> {
+        query_as::<_, Self>("
+            select
+                pk,
+                user_pk,
+                selector,
+                validator_hash,
+                is_active,
+                last_used_dt,
+                expired_dt,
+                created_dt
+            from RememberMeTokens
+            where selector = $1
+            and is_active = 1
+            and expired_dt > $2"
+        ).bind(selector)
+            .bind(Utc::now())
+            .fetch_one(db)
+            .await
+            .quick_match()
+    }
+    fn present(selector: &str, validator: &str) -> String {
+        format!("{}:{}", selector, validator)
+    }
+    /// Issues a token for `user`, returning it and the raw value to store on
+    /// the client
+    pub async fn issue(
+        db: &SqlitePool, user: &User, expires: Option<Duration>
+    ) -> Result<(Self, String), RustersError> {
+        let selector = Basic::rand()?.get_hash();
+        let validator = Basic::rand()?.get_hash();
+        let hashed = Secure::from_string(&validator)?;
+        let now = Utc::now();
+        let pk = query("
+            insert into RememberMeTokens (
+                user_pk,
+                selector,
+                validator_hash,
+                is_active,
+                expired_dt,
+                created_dt
+            ) values (
+                $1,
+                $2,
+                $3,
+                $4,
+                $5,
+                $6
+            )"
+        ).bind(user.get_pk())
+            .bind(&selector)
+            .bind(hashed.get_hash())
+            .bind(1_i64)
+            .bind(now + expires.unwrap_or_else(Self::default_expires))
+            .bind(now)
+            .execute(db)
+            .await
+            .quick_match()?
+            .last_insert_rowid();
+        let remember = Self::lookup_by_pk(db, pk).await?;
+        Ok((remember, Self::present(&selector, &validator)))
+    }
+    /// Starts a new logged in `Session` from `raw`, returning the session, its
+    /// user and the rotated raw value which replaces `raw` on the client. The
+    /// validator is rotated before the login, so for a user with two-factor
+    /// enabled the new value is still returned, with the session left pending
+    /// on `SessionCookie::complete_second_factor`
+    pub async fn login<'a>(
+        db: &SqlitePool, raw: &'a str
+    ) -> Result<(Session, User, String), RustersError> {
+        let (selector, validator) = match raw.split_once(':') {
+            Some(parts) => parts,
+            None => return Err(RustersError::InvalidTokenError),
+        };
+        let remember = Self::lookup(db, selector)
+            .await
+            .map_err(|_| RustersError::InvalidTokenError)?;
+        let user = User::lookup_by_pk(db, remember.user_pk).await?;
+        if !Secure::validate(validator, &remember.validator_hash)? {
+            Self::revoke_all(db, &user).await?;
+            return Err(RustersError::TokenReuseError);
+        }
+        let validator = Basic::rand()?.get_hash();
+        let hashed = Secure::from_string(&validator)?;
+        // a concurrent login rotating the same validator first means this
+        // presentation was a replay
+        let rotated = query("
+            update RememberMeTokens
+            set validator_hash = $1,
+                last_used_dt = $2
+            where pk = $3
+            and validator_hash = $4
+            and is_active = 1"
+        ).bind(hashed.get_hash())
+            .bind(Utc::now())
+            .bind(remember.pk)
+            .bind(&remember.validator_hash)
+            .execute(db)
+            .await
+            .quick_match()?
+            .rows_affected() == 1;
+        if !rotated {
+            Self::revoke_all(db, &user).await?;
+            return Err(RustersError::TokenReuseError);
+        }
+        let token = Token::basic(db, None).await?;
+        let session = Session::insert(db, &token).await?;
+        match SessionCookie::login(db, &session, &user).await {
+            Ok(_) | Err(RustersError::SecondFactorRequiredError) => {},
+            Err(e) => return Err(e),
+        }
+        Ok((session, user, Self::present(selector, &validator)))
+    }
+    /// Revokes this token, as on logout
+    pub async fn revoke(self, db: &SqlitePool) -> Result<(), RustersError> {
+        query("
+            update RememberMeTokens
+            set is_active = 0
+            where pk = $1"
+        ).bind(self.pk)
+            .execute(db)
+            .await
+            .quick_match()?;
+        Ok(())
+    }
+    /// Revokes every remember-me token of `user`
+    pub async fn revoke_all(
+        db: &SqlitePool, user: &User
+    ) -> Result<(), RustersError> {
+        query("
+            update RememberMeTokens
+            set is_active = 0
+            where user_pk = $1
+            and is_active = 1"
+        ).bind(user.get_pk())
+            .execute(db)
+            .await
+            .quick_match()?;
+        Ok(())
+    }
+}
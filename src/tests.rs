@@ -1,17 +1,48 @@
+#![allow(
+    clippy::needless_borrow,
+    clippy::redundant_static_lifetimes,
+    clippy::unnecessary_mut_passed,
+)]
 use {
     crate::{
+        ApiKey,
         Basic,
+        BreachedPasswords,
         ConsumableToken,
         Consumer,
+        EmailChange,
+        EmailVerification,
         Hash,
+        KeyState,
+        Keyring,
+        LoginPolicy,
+        MagicLink,
+        PasswordHistory,
+        PasswordPolicy,
+        PolicyViolation,
+        RecoveryCode,
+        RefreshToken,
+        RememberMe,
+        RustersError,
         RustersMigrator,
+        Secure,
         Session,
         SessionCookie,
+        SignedToken,
+        SigningKey,
         Token,
+        Totp,
         User,
     },
+    chrono::{
+        TimeZone,
+        Utc,
+    },
     sqlx::SqlitePool,
-    std::path::PathBuf,
+    std::{
+        cell::RefCell,
+        path::PathBuf,
+    },
 };
 fn get_file_name() -> String {
     let hash_res = Basic::rand();
@@ -288,3 +319,1333 @@ async fn lookup_session() {
     assert_eq!(s_lkp.get_pk(), s_ins.get_pk());
     delete_db_file_if_exists(&db_name);
 }
+#[async_std::test]
+async fn unknown_users_are_checked_against_the_dummy_hash() {
+    use crate::user::DUMMY_PASSWORD_HASH;
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let u = get_new_user(&db).await;
+    // the dummy must cost as much to check as a real hash, and a malformed
+    // dummy would fail the unknown user with a BcryptError instead
+    let setting = |hash: &str| {
+        let decoded = base64::decode_config(hash, base64::URL_SAFE).unwrap();
+        String::from_utf8(decoded).unwrap()[..7].to_string()
+    };
+    assert_eq!(setting(DUMMY_PASSWORD_HASH), setting(&u.get_password_hash()));
+    assert!(!Secure::validate(PASSWORD, DUMMY_PASSWORD_HASH).unwrap());
+    let v = User::validate(&db, "not_a_user", PASSWORD).await;
+    assert!(matches!(v, Err(RustersError::InvalidCredentialsError)));
+    delete_db_file_if_exists(&db_name);
+}
+fn strict_login_policy() -> LoginPolicy {
+    LoginPolicy {
+        max_failures: 2,
+        ..LoginPolicy::default()
+    }
+}
+#[async_std::test]
+async fn repeated_failures_lock_account() {
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let u = get_new_user(&db).await;
+    let p = strict_login_policy();
+    let v1 = User::validate_with_policy(&db, USERNAME, "wrong", None, &p).await;
+    assert!(matches!(v1, Err(RustersError::InvalidCredentialsError)));
+    let v2 = User::validate_with_policy(&db, USERNAME, "wrong", None, &p).await;
+    assert!(matches!(v2, Err(RustersError::AccountLocked { .. })));
+    let v3 = User::validate_with_policy(&db, USERNAME, PASSWORD, None, &p).await;
+    assert!(matches!(v3, Err(RustersError::AccountLocked { .. })));
+    u.unlock(&db).await.unwrap();
+    User::validate_with_policy(&db, USERNAME, PASSWORD, None, &p).await.unwrap();
+    for _ in 0..3 {
+        let v = User::validate(&db, USERNAME, "wrong").await;
+        assert!(matches!(v, Err(RustersError::InvalidCredentialsError)));
+    }
+    User::validate_with_policy(&db, USERNAME, PASSWORD, None, &p).await.unwrap();
+    delete_db_file_if_exists(&db_name);
+}
+#[async_std::test]
+async fn per_client_failures_do_not_lock_other_clients() {
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let _ = get_new_user(&db).await;
+    let p = LoginPolicy {
+        per_client: true,
+        ..strict_login_policy()
+    };
+    for _ in 0..2 {
+        let _ = User::validate_with_policy(&db, USERNAME, "wrong", Some("a"), &p)
+            .await;
+    }
+    let locked = User::validate_with_policy(&db, USERNAME, PASSWORD, Some("a"), &p)
+        .await;
+    assert!(matches!(locked, Err(RustersError::AccountLocked { .. })));
+    User::validate_with_policy(&db, USERNAME, PASSWORD, Some("b"), &p)
+        .await
+        .unwrap();
+    let still = User::validate_with_policy(&db, USERNAME, PASSWORD, Some("a"), &p)
+        .await;
+    assert!(matches!(still, Err(RustersError::AccountLocked { .. })));
+    delete_db_file_if_exists(&db_name);
+}
+#[test]
+fn lockout_backs_off_exponentially() {
+    let p = LoginPolicy::default();
+    assert_eq!(p.lockout_for(0), p.lockout);
+    assert_eq!(p.lockout_for(1), p.lockout * 2);
+    assert_eq!(p.lockout_for(3), p.lockout * 8);
+    assert_eq!(p.lockout_for(40), p.max_lockout);
+}
+#[test]
+fn password_policy_reports_violations() {
+    let p = PasswordPolicy {
+        require_uppercase: true,
+        require_digit: true,
+        ..PasswordPolicy::default()
+    };
+    assert!(p.check(USERNAME, "Correct-Horse-42").unwrap().is_empty());
+    assert_eq!(
+        p.check(USERNAME, "").unwrap(),
+        vec![
+            PolicyViolation::TooShort { min: 8 },
+            PolicyViolation::MissingUppercase,
+            PolicyViolation::MissingDigit,
+        ]
+    );
+    assert!(
+        p.check(USERNAME, "Xtest_user_1")
+            .unwrap()
+            .contains(&PolicyViolation::ContainsUsername)
+    );
+    assert!(
+        PasswordPolicy::default().check(USERNAME, "PASSWORD123")
+            .unwrap()
+            .contains(&PolicyViolation::Common)
+    );
+}
+#[async_std::test]
+async fn insert_rejects_weak_password() {
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let u = User::insert(&db, USERNAME, "").await;
+    assert!(matches!(u, Err(RustersError::WeakPassword(_))));
+    delete_db_file_if_exists(&db_name);
+}
+#[async_std::test]
+async fn change_password() {
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let mut u = get_new_user(&db).await;
+    let weak = u.change_password(&db, "letmein").await;
+    assert!(matches!(weak, Err(RustersError::WeakPassword(_))));
+    u.change_password(&db, "a_new_password_2").await.unwrap();
+    User::validate(&db, USERNAME, "a_new_password_2").await.unwrap();
+    delete_db_file_if_exists(&db_name);
+}
+fn create_breach_file<'a>(name: &'a str, passwords: &[&'a str]) -> PathBuf {
+    let path = PathBuf::from(
+        format!(
+            "{}/test_dbs/{}.txt",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        )
+    );
+    let mut lines = (0..200).map(|i| format!("filler_{}", i))
+        .chain(passwords.iter().map(|p| p.to_string()))
+        .enumerate()
+        .map(|(i, p)| format!("{}:{}", BreachedPasswords::sha1_hex(p), i + 1))
+        .collect::<Vec<String>>();
+    lines.sort();
+    std::fs::write(&path, lines.join("\r\n")).unwrap();
+    path
+}
+#[test]
+fn breached_passwords_lookup() {
+    let name = get_file_name();
+    let path = create_breach_file(&name, &["hunter2hunter2", "zzzz"]);
+    let b = BreachedPasswords::new(&path);
+    assert!(b.lookup("hunter2hunter2").unwrap().is_some());
+    assert!(b.lookup("zzzz").unwrap().is_some());
+    for i in 0..200 {
+        assert!(b.lookup(format!("filler_{}", i)).unwrap().is_some());
+    }
+    assert!(b.lookup(PASSWORD).unwrap().is_none());
+    std::fs::remove_file(path).unwrap();
+}
+#[async_std::test]
+async fn insert_rejects_breached_password() {
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let path = create_breach_file(&db_name, &["hunter2hunter2"]);
+    let p = PasswordPolicy::default()
+        .with_check(BreachedPasswords::new(&path));
+    let u = User::insert_with_policy(&db, USERNAME, "hunter2hunter2", &p).await;
+    match u {
+        Err(RustersError::WeakPassword(v)) => {
+            assert!(matches!(v[0], PolicyViolation::Breached { .. }));
+        },
+        _ => panic!("Breached password was accepted"),
+    }
+    User::insert_with_policy(&db, USERNAME, PASSWORD, &p).await.unwrap();
+    std::fs::remove_file(path).unwrap();
+    delete_db_file_if_exists(&db_name);
+}
+#[async_std::test]
+async fn change_password_rejects_recent_passwords() {
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let p = PasswordPolicy {
+        history_depth: 2,
+        ..PasswordPolicy::default()
+    };
+    let mut u = User::insert_with_policy(&db, USERNAME, PASSWORD, &p)
+        .await
+        .unwrap();
+    let same = u.change_password_with_policy(&db, PASSWORD, &p).await;
+    match same {
+        Err(RustersError::WeakPassword(v)) => {
+            assert_eq!(v, vec![PolicyViolation::Reused]);
+        },
+        _ => panic!("Current password was reused"),
+    }
+    u.change_password_with_policy(&db, "second_password_2", &p).await.unwrap();
+    let first = u.change_password_with_policy(&db, PASSWORD, &p).await;
+    assert!(matches!(first, Err(RustersError::WeakPassword(_))));
+    u.change_password_with_policy(&db, "third_password_3", &p).await.unwrap();
+    assert_eq!(PasswordHistory::recent(&db, &u, 10).await.unwrap().len(), 2);
+    u.change_password_with_policy(&db, PASSWORD, &p).await.unwrap();
+    delete_db_file_if_exists(&db_name);
+}
+#[test]
+fn totp_matches_rfc_6238_vectors() {
+    // the ascii secret "12345678901234567890" from RFC 6238 appendix B
+    let secret = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+    let vectors = [
+        (59, "287082"),
+        (1111111109, "081804"),
+        (1111111111, "050471"),
+        (1234567890, "005924"),
+        (2000000000, "279037"),
+    ];
+    for (time, code) in vectors {
+        let at = Utc.timestamp_opt(time, 0).unwrap();
+        assert_eq!(Totp::generate(secret, at).unwrap(), code);
+    }
+}
+#[async_std::test]
+async fn login_requires_totp_once_enrolled() {
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let u = get_new_user(&db).await;
+    let mut totp = Totp::enroll(&db, &u).await.unwrap();
+    let uri = totp.provisioning_uri("Rusters Test", &u);
+    assert!(uri.starts_with("otpauth://totp/Rusters%20Test:test_user_1?"));
+    assert!(uri.contains(&format!("secret={}", totp.get_secret())));
+    assert!(!Totp::is_enabled(&db, &u).await.unwrap());
+    let now = Utc::now();
+    totp.confirm(&db, &totp.code_at(now).unwrap(), None).await.unwrap();
+    assert!(Totp::is_enabled(&db, &u).await.unwrap());
+    let t = Token::basic(&db, None).await.unwrap();
+    let s = get_session(&db, &t).await;
+    let l = SessionCookie::login(&db, &s, &u).await;
+    assert!(matches!(l, Err(RustersError::SecondFactorRequiredError)));
+    assert!(!check_user_logged_in(&db, &s).await);
+    assert!(SessionCookie::has_second_factor_cookie(&db, &s).await.unwrap());
+    let replay = SessionCookie::complete_second_factor(
+        &db, &s, &totp.code_at(now).unwrap(), None
+    ).await;
+    assert!(matches!(replay, Err(RustersError::InvalidSecondFactorError)));
+    let next = totp.code_at(now + chrono::Duration::seconds(Totp::PERIOD))
+        .unwrap();
+    SessionCookie::complete_second_factor(&db, &s, &next, None).await.unwrap();
+    assert!(check_user_logged_in(&db, &s).await);
+    assert!(!SessionCookie::has_second_factor_cookie(&db, &s).await.unwrap());
+    delete_db_file_if_exists(&db_name);
+}
+#[async_std::test]
+async fn recovery_codes_are_single_use() {
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let u = get_new_user(&db).await;
+    let mut totp = Totp::enroll(&db, &u).await.unwrap();
+    totp.confirm(&db, &totp.code_at(Utc::now()).unwrap(), None).await.unwrap();
+    let codes = RecoveryCode::generate(&db, &u).await.unwrap();
+    assert_eq!(codes.len(), RecoveryCode::BATCH_SIZE);
+    let t = Token::basic(&db, None).await.unwrap();
+    let s = get_session(&db, &t).await;
+    let _ = SessionCookie::login(&db, &s, &u).await;
+    SessionCookie::complete_with_recovery_code(&db, &s, &codes[0])
+        .await
+        .unwrap();
+    assert!(check_user_logged_in(&db, &s).await);
+    let reused = RecoveryCode::redeem(&db, &u, &codes[0]).await;
+    assert!(matches!(reused, Err(RustersError::InvalidSecondFactorError)));
+    let remaining = RecoveryCode::lookup_remaining(&db, &u).await.unwrap();
+    assert_eq!(remaining.len(), RecoveryCode::BATCH_SIZE - 1);
+    let new_codes = RecoveryCode::generate(&db, &u).await.unwrap();
+    let old = RecoveryCode::redeem(&db, &u, &codes[1]).await;
+    assert!(matches!(old, Err(RustersError::InvalidSecondFactorError)));
+    RecoveryCode::redeem(&db, &u, &new_codes[1]).await.unwrap();
+    delete_db_file_if_exists(&db_name);
+}
+#[async_std::test]
+async fn recovery_codes_sharing_a_selector_both_redeem() {
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let u = get_new_user(&db).await;
+    let codes = RecoveryCode::generate(&db, &u).await.unwrap();
+    let mut selectors = codes.iter()
+        .map(|c| c.split_once('-').unwrap().0)
+        .collect::<Vec<&str>>();
+    selectors.sort();
+    selectors.dedup();
+    assert_eq!(selectors.len(), RecoveryCode::BATCH_SIZE);
+    sqlx::query("update RecoveryCodes set selector = 'aaaa'")
+        .execute(&db)
+        .await
+        .unwrap();
+    for code in &codes[..2] {
+        let secret = code.split_once('-').unwrap().1;
+        RecoveryCode::redeem(&db, &u, &format!("aaaa-{}", secret)).await.unwrap();
+    }
+    delete_db_file_if_exists(&db_name);
+}
+#[async_std::test]
+async fn wrong_second_factors_lock_account() {
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let u = get_new_user(&db).await;
+    let mut totp = Totp::enroll(&db, &u).await.unwrap();
+    let now = Utc::now();
+    totp.confirm(&db, &totp.code_at(now).unwrap(), None).await.unwrap();
+    let t = Token::basic(&db, None).await.unwrap();
+    let s = get_session(&db, &t).await;
+    let _ = SessionCookie::login(&db, &s, &u).await;
+    let p = strict_login_policy();
+    let c1 = SessionCookie::complete_second_factor_with_policy(
+        &db, &s, "wrong", None, None, &p
+    ).await;
+    assert!(matches!(c1, Err(RustersError::InvalidSecondFactorError)));
+    let c2 = SessionCookie::complete_second_factor_with_policy(
+        &db, &s, "wrong", None, None, &p
+    ).await;
+    assert!(matches!(c2, Err(RustersError::AccountLocked { .. })));
+    assert!(!SessionCookie::has_second_factor_cookie(&db, &s).await.unwrap());
+    let next = totp.code_at(now + chrono::Duration::seconds(Totp::PERIOD))
+        .unwrap();
+    let c3 = SessionCookie::complete_second_factor_with_policy(
+        &db, &s, &next, None, None, &p
+    ).await;
+    assert!(matches!(c3, Err(RustersError::NotLoggedInError)));
+    let v = User::validate_with_policy(&db, USERNAME, PASSWORD, None, &p).await;
+    assert!(matches!(v, Err(RustersError::AccountLocked { .. })));
+    assert!(!check_user_logged_in(&db, &s).await);
+    let p = LoginPolicy {
+        per_client: true,
+        ..strict_login_policy()
+    };
+    let _ = SessionCookie::login(&db, &s, &u).await;
+    let r1 = SessionCookie::complete_with_recovery_code_with_policy(
+        &db, &s, "wrong", Some("a"), &p
+    ).await;
+    assert!(matches!(r1, Err(RustersError::InvalidSecondFactorError)));
+    let r2 = SessionCookie::complete_with_recovery_code_with_policy(
+        &db, &s, "wrong", Some("a"), &p
+    ).await;
+    assert!(matches!(r2, Err(RustersError::AccountLocked { .. })));
+    assert!(!SessionCookie::has_second_factor_cookie(&db, &s).await.unwrap());
+    User::validate_with_policy(&db, USERNAME, PASSWORD, Some("b"), &p)
+        .await
+        .unwrap();
+    delete_db_file_if_exists(&db_name);
+}
+#[async_std::test]
+async fn verify_email() {
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let mut u = get_new_user(&db).await;
+    let none = EmailVerification::issue(&db, &u, None).await;
+    assert!(matches!(none, Err(RustersError::MissingEmailError)));
+    u.set_email(&db, "test@example.com").await.unwrap();
+    assert!(!u.get_is_email_verified());
+    let raw = EmailVerification::issue(&db, &u, None).await.unwrap();
+    let verified = EmailVerification::redeem(&db, &raw).await.unwrap();
+    assert_eq!(verified.get_pk(), u.get_pk());
+    assert!(verified.get_is_email_verified());
+    let again = EmailVerification::redeem(&db, &raw).await;
+    assert!(matches!(again, Err(RustersError::InvalidTokenError)));
+    let raw = EmailVerification::issue(&db, &u, None).await.unwrap();
+    u.set_email(&db, "other@example.com").await.unwrap();
+    let stale = EmailVerification::redeem(&db, &raw).await;
+    assert!(matches!(stale, Err(RustersError::InvalidTokenError)));
+    delete_db_file_if_exists(&db_name);
+}
+#[async_std::test]
+async fn change_email() {
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let mut u = get_new_user(&db).await;
+    u.set_email(&db, "old@example.com").await.unwrap();
+    let raw_1 = EmailChange::request(&db, &u, "first@example.com", None)
+        .await
+        .unwrap();
+    let raw_2 = EmailChange::request(&db, &u, "second@example.com", None)
+        .await
+        .unwrap();
+    assert_eq!(EmailChange::lookup_pending(&db, &u).await.unwrap().len(), 2);
+    let u = User::lookup_by_pk(&db, u.get_pk()).await.unwrap();
+    assert_eq!(u.get_email().unwrap(), "old@example.com");
+    let notified = RefCell::new(Vec::new());
+    let hook = |_: &User, old: &str, new: &str| {
+        notified.borrow_mut().push((old.to_string(), new.to_string()));
+    };
+    let u = EmailChange::confirm(&db, &raw_2, Some(&hook)).await.unwrap();
+    assert_eq!(u.get_email().unwrap(), "second@example.com");
+    assert!(u.get_is_email_verified());
+    assert_eq!(
+        notified.into_inner(),
+        vec![("old@example.com".to_string(), "second@example.com".to_string())]
+    );
+    let other = EmailChange::confirm(&db, &raw_1, None).await;
+    assert!(matches!(other, Err(RustersError::InvalidTokenError)));
+    assert!(EmailChange::lookup_pending(&db, &u).await.unwrap().is_empty());
+    delete_db_file_if_exists(&db_name);
+}
+#[async_std::test]
+async fn magic_link_login() {
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let u = User::insert_passwordless(&db, USERNAME).await.unwrap();
+    let t = Token::basic(&db, None).await.unwrap();
+    let s = get_session(&db, &t).await;
+    let (_, secret) = MagicLink::issue(&db, &u, None).await.unwrap();
+    let l = MagicLink::redeem(&db, &secret, &s).await.unwrap();
+    assert_eq!(l.get_pk(), u.get_pk());
+    assert!(check_user_logged_in(&db, &s).await);
+    let again = MagicLink::redeem(&db, &secret, &s).await;
+    assert!(matches!(again, Err(RustersError::InvalidTokenError)));
+    delete_db_file_if_exists(&db_name);
+}
+#[async_std::test]
+async fn magic_link_bound_to_session() {
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let u = User::insert_passwordless(&db, USERNAME).await.unwrap();
+    let t_1 = Token::basic(&db, None).await.unwrap();
+    let s_1 = get_session(&db, &t_1).await;
+    let t_2 = Token::basic(&db, None).await.unwrap();
+    let s_2 = get_session(&db, &t_2).await;
+    let (_, secret) = MagicLink::issue_for_session(&db, &u, None, &s_1)
+        .await
+        .unwrap();
+    let other = MagicLink::redeem(&db, &secret, &s_2).await;
+    assert!(matches!(other, Err(RustersError::InvalidTokenError)));
+    assert!(!check_user_logged_in(&db, &s_2).await);
+    MagicLink::redeem(&db, &secret, &s_1).await.unwrap();
+    assert!(check_user_logged_in(&db, &s_1).await);
+    delete_db_file_if_exists(&db_name);
+}
+#[async_std::test]
+async fn passwordless_user_never_validates() {
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let mut u = User::insert_passwordless(&db, USERNAME).await.unwrap();
+    assert!(PasswordHistory::recent(&db, &u, 10).await.unwrap().is_empty());
+    let hash = u.get_password_hash();
+    for password in [ "", "!", hash.as_str(), PASSWORD ] {
+        let v = User::validate(&db, USERNAME, password).await;
+        assert!(matches!(v, Err(RustersError::InvalidCredentialsError)));
+        u.unlock(&db).await.unwrap();
+    }
+    u.change_password(&db, PASSWORD).await.unwrap();
+    User::validate(&db, USERNAME, PASSWORD).await.unwrap();
+    delete_db_file_if_exists(&db_name);
+}
+#[async_std::test]
+async fn remember_me_rotates_and_detects_reuse() {
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let u = get_new_user(&db).await;
+    let (_, raw_1) = RememberMe::issue(&db, &u, None).await.unwrap();
+    let (s, l, raw_2) = RememberMe::login(&db, &raw_1).await.unwrap();
+    assert_eq!(l.get_pk(), u.get_pk());
+    assert!(check_user_logged_in(&db, &s).await);
+    assert_ne!(raw_1, raw_2);
+    let stolen = RememberMe::login(&db, &raw_1).await;
+    assert!(matches!(stolen, Err(RustersError::TokenReuseError)));
+    let revoked = RememberMe::login(&db, &raw_2).await;
+    assert!(matches!(revoked, Err(RustersError::InvalidTokenError)));
+    delete_db_file_if_exists(&db_name);
+}
+#[async_std::test]
+async fn remember_me_rotates_for_second_factor_users() {
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let u = get_new_user(&db).await;
+    let mut totp = Totp::enroll(&db, &u).await.unwrap();
+    let now = Utc::now();
+    totp.confirm(&db, &totp.code_at(now).unwrap(), None).await.unwrap();
+    let (_, raw_1) = RememberMe::issue(&db, &u, None).await.unwrap();
+    let (s, _, raw_2) = RememberMe::login(&db, &raw_1).await.unwrap();
+    assert_ne!(raw_1, raw_2);
+    assert!(!check_user_logged_in(&db, &s).await);
+    assert!(SessionCookie::has_second_factor_cookie(&db, &s).await.unwrap());
+    let next = totp.code_at(now + chrono::Duration::seconds(Totp::PERIOD))
+        .unwrap();
+    SessionCookie::complete_second_factor(&db, &s, &next, None).await.unwrap();
+    assert!(check_user_logged_in(&db, &s).await);
+    let reused = RememberMe::login(&db, &raw_1).await;
+    assert!(matches!(reused, Err(RustersError::TokenReuseError)));
+    delete_db_file_if_exists(&db_name);
+}
+#[async_std::test]
+async fn refresh_token_rotation_and_reuse() {
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let u = get_new_user(&db).await;
+    let (a_1, r_1) = RefreshToken::issue(&db, &u, None, None).await.unwrap();
+    let a_u = RefreshToken::authenticate(&db, &a_1.get_hash()).await.unwrap();
+    assert_eq!(a_u.get_pk(), u.get_pk());
+    let (a_2, r_2) = RefreshToken::refresh(&db, &r_1.get_hash(), None, None)
+        .await
+        .unwrap();
+    RefreshToken::authenticate(&db, &a_2.get_hash()).await.unwrap();
+    let reused = RefreshToken::refresh(&db, &r_1.get_hash(), None, None).await;
+    assert!(matches!(reused, Err(RustersError::TokenReuseError)));
+    let revoked = RefreshToken::refresh(&db, &r_2.get_hash(), None, None).await;
+    assert!(matches!(revoked, Err(RustersError::InvalidTokenError)));
+    let a_r = RefreshToken::authenticate(&db, &a_2.get_hash()).await;
+    assert!(matches!(a_r, Err(RustersError::InvalidTokenError)));
+    delete_db_file_if_exists(&db_name);
+}
+#[async_std::test]
+async fn scoped_token() {
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let t = Token::insert_with_scopes(
+        &db, Basic::rand().unwrap(), None, &["read", "read"]
+    ).await.unwrap();
+    assert_eq!(t.lookup_scopes(&db).await.unwrap(), vec!["read".to_string()]);
+    assert!(t.has_scope(&db, "read").await.unwrap());
+    assert!(!t.has_scope(&db, "write").await.unwrap());
+    t.require_scopes(&db, &["read"]).await.unwrap();
+    match t.require_scopes(&db, &["read", "write"]).await {
+        Err(RustersError::InsufficientScope(missing)) => {
+            assert_eq!(missing, vec!["write".to_string()]);
+        },
+        _ => panic!("Missing scope was allowed"),
+    }
+    delete_db_file_if_exists(&db_name);
+}
+#[async_std::test]
+async fn api_keys() {
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let u = get_new_user(&db).await;
+    let (k, raw) = ApiKey::create(&db, &u, "ci", None, &["read"])
+        .await
+        .unwrap();
+    assert!(raw.starts_with(&format!("rk_{}_", k.get_prefix())));
+    assert!(k.get_last_used_dt().is_none());
+    let (a_u, a_k) = ApiKey::authenticate(&db, &raw).await.unwrap();
+    assert_eq!(a_u.get_pk(), u.get_pk());
+    assert_eq!(a_k.get_pk(), k.get_pk());
+    assert!(a_k.get_last_used_dt().is_some());
+    a_k.require_scopes(&db, &["read"]).await.unwrap();
+    assert!(a_k.require_scopes(&db, &["write"]).await.is_err());
+    let wrong = format!("rk_{}_not_the_secret", k.get_prefix());
+    assert!(matches!(
+        ApiKey::authenticate(&db, &wrong).await,
+        Err(RustersError::InvalidTokenError)
+    ));
+    let keys = ApiKey::list(&db, &u).await.unwrap();
+    assert_eq!(keys.len(), 1);
+    keys.into_iter().next().unwrap().revoke(&db).await.unwrap();
+    assert!(ApiKey::list(&db, &u).await.unwrap().is_empty());
+    assert!(ApiKey::authenticate(&db, &raw).await.is_err());
+    delete_db_file_if_exists(&db_name);
+}
+#[async_std::test]
+async fn signed_tokens_verify_across_rotation() {
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let u = get_new_user(&db).await;
+    let t = Token::basic(&db, None).await.unwrap();
+    let s = get_session(&db, &t).await;
+    let mut keys = Keyring::new();
+    assert!(matches!(
+        SignedToken::issue(&keys, &s, &u, None),
+        Err(RustersError::NoSigningKeyError)
+    ));
+    keys.rotate("k1", b"first secret".to_vec());
+    let signed_1 = SignedToken::issue(&keys, &s, &u, None).unwrap();
+    keys.rotate("k2", b"second secret".to_vec());
+    let signed_2 = SignedToken::issue(&keys, &s, &u, None).unwrap();
+    let v_1 = SignedToken::verify(&keys, &signed_1).unwrap();
+    assert_eq!(v_1.get_key_id(), "k1");
+    assert_eq!(v_1.get_subject(), u.get_pk().to_string());
+    assert_eq!(v_1.get_session_pk(), s.get_pk());
+    assert_eq!(SignedToken::verify(&keys, &signed_2).unwrap().get_key_id(), "k2");
+    let mut tampered = signed_2.clone();
+    tampered.insert(0, 'e');
+    assert!(SignedToken::verify(&keys, &tampered).is_err());
+    keys.remove("k1");
+    assert!(SignedToken::verify(&keys, &signed_1).is_err());
+    let expired = SignedToken::issue(
+        &keys, &s, &u, Some(chrono::Duration::seconds(-1))
+    ).unwrap();
+    assert!(SignedToken::verify(&keys, &expired).is_err());
+    SignedToken::verify_unrevoked(&db, &keys, &signed_2).await.unwrap();
+    t.expire(&db).await.unwrap();
+    SignedToken::verify(&keys, &signed_2).unwrap();
+    assert!(SignedToken::verify_unrevoked(&db, &keys, &signed_2).await.is_err());
+    delete_db_file_if_exists(&db_name);
+}
+#[async_std::test]
+async fn signing_keys_persist_and_rotate() {
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let u = get_new_user(&db).await;
+    let t = Token::basic(&db, None).await.unwrap();
+    let s = get_session(&db, &t).await;
+    let master = [7_u8; 32];
+    assert!(Keyring::load(&db, &master).await.unwrap().active().is_none());
+    let first = SigningKey::rotate_if_due(&db, &master, None, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(SigningKey::rotate_if_due(&db, &master, None, None).await.unwrap().is_none());
+    let signed_1 = SignedToken::issue(
+        &Keyring::load(&db, &master).await.unwrap(), &s, &u, None
+    ).unwrap();
+    let second = SigningKey::rotate_if_due(
+        &db, &master, Some(chrono::Duration::zero()), None
+    ).await.unwrap().unwrap();
+    let first = SigningKey::lookup_by_kid(&db, &first.get_kid()).await.unwrap();
+    assert_eq!(first.get_state(), KeyState::Retiring);
+    assert_eq!(second.get_state(), KeyState::Active);
+    let keys = Keyring::load(&db, &master).await.unwrap();
+    assert_eq!(keys.active().unwrap().0, second.get_kid());
+    assert_eq!(keys.get(&first.get_kid()).unwrap(), first.secret(&master).unwrap());
+    SignedToken::verify(&keys, &signed_1).unwrap();
+    assert!(matches!(
+        Keyring::load(&db, &[8_u8; 32]).await,
+        Err(RustersError::MasterKeyError)
+    ));
+    SigningKey::rotate(&db, &master, Some(chrono::Duration::seconds(-1))).await.unwrap();
+    SigningKey::retire_expired(&db).await.unwrap();
+    let second = SigningKey::lookup_by_kid(&db, &second.get_kid()).await.unwrap();
+    assert_eq!(second.get_state(), KeyState::Retired);
+    assert!(second.get_retired_dt().is_some());
+    let keys = Keyring::load(&db, &master).await.unwrap();
+    assert_eq!(keys.key_ids().len(), 2);
+    assert!(keys.get(&second.get_kid()).is_none());
+    assert!(SignedToken::verify(&keys, &signed_1).is_ok());
+    delete_db_file_if_exists(&db_name);
+}
+#[async_std::test]
+async fn oauth_code_and_client_credentials_grants() {
+    use crate::{
+        AuthorizationRequest,
+        ErrorResponse,
+        OAuthErrorCode,
+        OAuthServer,
+        TokenRequest,
+    };
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let u = get_new_user(&db).await;
+    let server = OAuthServer::new();
+    let redirect = "https://spa.example/callback";
+    let (spa, secret) = Consumer::register(&db, "spa", &[redirect], &[], false)
+        .await
+        .unwrap();
+    assert!(secret.is_none() && !spa.get_is_confidential());
+    let (_, secret) = Consumer::register(&db, "backend", &[], &["reports"], true)
+        .await
+        .unwrap();
+    let secret = secret.unwrap();
+    // RFC 7636 appendix B
+    let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+    let challenge = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+    assert_eq!(crate::token::oauth::pkce_challenge(verifier), challenge);
+    let code_of = |e: RustersError| OAuthErrorCode::from_error(&e);
+    let mut authorize = AuthorizationRequest {
+        response_type: "code".to_string(),
+        client_id: "spa".to_string(),
+        redirect_uri: Some("https://evil.example/".to_string()),
+        scope: Some("read write".to_string()),
+        state: Some("xyz 1".to_string()),
+        code_challenge: Some(challenge.to_string()),
+        code_challenge_method: Some("S256".to_string()),
+        nonce: None,
+    };
+    let err = server.authorize(&db, &u, &authorize).await.unwrap_err();
+    assert_eq!(code_of(err), OAuthErrorCode::InvalidRequest);
+    authorize.redirect_uri = None;
+    authorize.code_challenge_method = None;
+    let err = server.authorize(&db, &u, &authorize).await.unwrap_err();
+    assert_eq!(code_of(err), OAuthErrorCode::InvalidRequest);
+    authorize.code_challenge_method = Some("S256".to_string());
+    let grant = server.authorize(&db, &u, &authorize).await.unwrap();
+    assert_eq!(
+        grant.get_location(),
+        format!("{}?code={}&state=xyz%201", redirect, grant.get_code())
+    );
+    let mut exchange = TokenRequest {
+        grant_type: "authorization_code".to_string(),
+        client_id: Some("spa".to_string()),
+        code: Some(grant.get_code()),
+        redirect_uri: Some(redirect.to_string()),
+        code_verifier: Some("not the verifier".to_string()),
+        ..Default::default()
+    };
+    let err = server.token(&db, &exchange).await.unwrap_err();
+    assert_eq!(ErrorResponse::from(&err).error, "invalid_grant");
+    exchange.code_verifier = Some(verifier.to_string());
+    let issued = server.token(&db, &exchange).await.unwrap();
+    assert_eq!(issued.scope, "read write");
+    assert_eq!(issued.token_type, "Bearer");
+    assert_eq!(RefreshToken::authenticate(&db, &issued.access_token).await.unwrap().get_pk(), u.get_pk());
+    let err = server.token(&db, &exchange).await.unwrap_err();
+    assert_eq!(code_of(err), OAuthErrorCode::InvalidGrant);
+    authorize.redirect_uri = Some(redirect.to_string());
+    let explicit = server.authorize(&db, &u, &authorize).await.unwrap();
+    exchange.code = Some(explicit.get_code());
+    exchange.redirect_uri = None;
+    let err = server.token(&db, &exchange).await.unwrap_err();
+    assert_eq!(code_of(err), OAuthErrorCode::InvalidGrant);
+    exchange.redirect_uri = Some(redirect.to_string());
+    server.token(&db, &exchange).await.unwrap();
+    let mut refresh = TokenRequest {
+        grant_type: "refresh_token".to_string(),
+        client_id: Some("backend".to_string()),
+        client_secret: Some(secret.clone()),
+        refresh_token: issued.refresh_token.clone(),
+        scope: Some("read".to_string()),
+        ..Default::default()
+    };
+    let err = server.token(&db, &refresh).await.unwrap_err();
+    assert_eq!(code_of(err), OAuthErrorCode::InvalidGrant);
+    refresh.client_id = Some("spa".to_string());
+    refresh.client_secret = None;
+    refresh.scope = Some("read admin".to_string());
+    let err = server.token(&db, &refresh).await.unwrap_err();
+    assert_eq!(code_of(err), OAuthErrorCode::InvalidScope);
+    refresh.scope = Some("read".to_string());
+    let refreshed = server.token(&db, &refresh).await.unwrap();
+    assert_eq!(refreshed.scope, "read");
+    let access = Token::possible(&db, &refreshed.access_token).await.unwrap().unwrap();
+    assert!(!access.has_scope(&db, "write").await.unwrap());
+    let err = server.token(&db, &refresh).await.unwrap_err();
+    assert_eq!(code_of(err), OAuthErrorCode::InvalidGrant);
+    assert!(Token::possible(&db, &refreshed.access_token).await.unwrap().is_none());
+    let mut credentials = TokenRequest {
+        grant_type: "client_credentials".to_string(),
+        client_id: Some("backend".to_string()),
+        client_secret: Some("wrong".to_string()),
+        scope: Some("reports".to_string()),
+        ..Default::default()
+    };
+    let err = server.token(&db, &credentials).await.unwrap_err();
+    assert_eq!(code_of(err), OAuthErrorCode::InvalidClient);
+    credentials.client_secret = Some(secret);
+    credentials.scope = Some("reports admin".to_string());
+    let err = server.token(&db, &credentials).await.unwrap_err();
+    assert_eq!(code_of(err), OAuthErrorCode::InvalidScope);
+    credentials.scope = Some("reports".to_string());
+    let issued = server.token(&db, &credentials).await.unwrap();
+    assert!(issued.refresh_token.is_none());
+    assert_eq!(issued.scope, "reports");
+    credentials.client_id = Some("spa".to_string());
+    credentials.client_secret = None;
+    let err = server.token(&db, &credentials).await.unwrap_err();
+    assert_eq!(code_of(err), OAuthErrorCode::UnauthorizedClient);
+    credentials.grant_type = "password".to_string();
+    let err = server.token(&db, &credentials).await.unwrap_err();
+    assert_eq!(code_of(err), OAuthErrorCode::UnsupportedGrantType);
+    delete_db_file_if_exists(&db_name);
+}
+async fn get_oauth_tokens(
+    db: &SqlitePool
+) -> (crate::TokenResponse, crate::TokenResponse, String) {
+    use crate::{ AuthorizationRequest, OAuthServer, TokenRequest, };
+    let u = get_new_user(db).await;
+    let server = OAuthServer::new();
+    Consumer::register(db, "spa", &["https://spa.example/"], &[], false).await.unwrap();
+    let (_, secret) = Consumer::register(db, "backend", &[], &["reports"], true)
+        .await
+        .unwrap();
+    let secret = secret.unwrap();
+    let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+    let authorize = AuthorizationRequest {
+        response_type: "code".to_string(),
+        client_id: "spa".to_string(),
+        scope: Some("read".to_string()),
+        code_challenge: Some(crate::token::oauth::pkce_challenge(verifier)),
+        code_challenge_method: Some("S256".to_string()),
+        ..Default::default()
+    };
+    let code = server.authorize(db, &u, &authorize).await.unwrap().get_code();
+    let user_tokens = server.token(db, &TokenRequest {
+        grant_type: "authorization_code".to_string(),
+        client_id: Some("spa".to_string()),
+        code: Some(code),
+        code_verifier: Some(verifier.to_string()),
+        ..Default::default()
+    }).await.unwrap();
+    let client_tokens = server.token(db, &TokenRequest {
+        grant_type: "client_credentials".to_string(),
+        client_id: Some("backend".to_string()),
+        client_secret: Some(secret.clone()),
+        scope: Some("reports".to_string()),
+        ..Default::default()
+    }).await.unwrap();
+    (user_tokens, client_tokens, secret)
+}
+#[async_std::test]
+async fn introspect_and_revoke_tokens() {
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let (user_tokens, client_tokens, _) = get_oauth_tokens(&db).await;
+    let u = User::lookup_by_pk(&db, 1).await.unwrap();
+    let client = Token::introspect(&db, &client_tokens.access_token).await.unwrap();
+    assert!(client.active);
+    assert_eq!(client.client_id.as_deref(), Some("backend"));
+    assert_eq!(client.scope.as_deref(), Some("reports"));
+    assert!(client.sub.is_none());
+    assert_eq!(client.token_type.as_deref(), Some("Bearer"));
+    assert_eq!(client.token_use.as_deref(), Some("access_token"));
+    let refresh = user_tokens.refresh_token.unwrap();
+    let user = Token::introspect(&db, &refresh).await.unwrap();
+    assert_eq!(user.sub, Some(u.get_pk().to_string()));
+    assert_eq!(user.client_id.as_deref(), Some("spa"));
+    assert_eq!(user.token_use.as_deref(), Some("refresh_token"));
+    assert!(user.exp.unwrap() > user.iat.unwrap());
+    let unknown = Token::introspect(&db, "not a token").await.unwrap();
+    assert!(!unknown.active && unknown.client_id.is_none());
+    let session_token = Token::basic(&db, None).await.unwrap();
+    let session = Token::introspect(&db, &session_token.get_hash()).await.unwrap();
+    assert!(!session.active && session.client_id.is_none());
+    let spa = Consumer::lookup(&db, "spa").await.unwrap();
+    let backend = Consumer::lookup(&db, "backend").await.unwrap();
+    assert!(matches!(
+        Token::revoke(&db, &backend, &refresh).await,
+        Err(RustersError::OAuthError(crate::OAuthErrorCode::UnauthorizedClient))
+    ));
+    Token::revoke(&db, &spa, &refresh).await.unwrap();
+    assert!(!Token::introspect(&db, &refresh).await.unwrap().active);
+    assert!(!Token::introspect(&db, &user_tokens.access_token).await.unwrap().active);
+    Token::revoke(&db, &spa, &refresh).await.unwrap();
+    Token::revoke(&db, &backend, &client_tokens.access_token).await.unwrap();
+    assert!(!Token::introspect(&db, &client_tokens.access_token).await.unwrap().active);
+    delete_db_file_if_exists(&db_name);
+}
+#[cfg(feature = "server")]
+#[async_std::test]
+async fn server_introspects_and_revokes_over_http() {
+    use {
+        crate::{ Introspection, RustersServer, },
+        tide::http::{ Method, Request, Response, Url, },
+    };
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let (user_tokens, _, secret) = get_oauth_tokens(&db).await;
+    let app = RustersServer::app(db.clone());
+    let post = |path: &str, body: String, basic: Option<(&str, &str)>| {
+        let url = Url::parse("http://localhost/").unwrap().join(path).unwrap();
+        let mut req = Request::new(Method::Post, url);
+        req.set_body(body);
+        req.set_content_type(tide::http::mime::FORM);
+        if let Some((id, secret)) = basic {
+            let encoded = base64::encode(format!("{}:{}", id, secret));
+            req.insert_header("Authorization", format!("Basic {}", encoded));
+        }
+        req
+    };
+    let access = format!("token={}", urlencoding::encode(&user_tokens.access_token));
+    let res: Response = app.respond(post("introspect", access.clone(), None)).await.unwrap();
+    assert_eq!(res.status(), 401);
+    let mut res: Response = app.respond(
+        post("introspect", access.clone(), Some(("backend", &secret)))
+    ).await.unwrap();
+    assert_eq!(res.status(), 200);
+    let introspection: Introspection = res.body_json().await.unwrap();
+    assert!(introspection.active);
+    assert_eq!(introspection.client_id.as_deref(), Some("spa"));
+    let res: Response = app.respond(
+        post("revoke", format!("{}&client_id=spa", access), None)
+    ).await.unwrap();
+    assert_eq!(res.status(), 200);
+    let mut res: Response = app.respond(
+        post("introspect", format!(
+            "{}&client_id=backend&client_secret={}", access, urlencoding::encode(&secret)
+        ), None)
+    ).await.unwrap();
+    let introspection: Introspection = res.body_json().await.unwrap();
+    assert!(!introspection.active);
+    delete_db_file_if_exists(&db_name);
+}
+#[async_std::test]
+async fn consumer_secrets_rotate_and_deactivate() {
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let (backend, first) = Consumer::register(&db, "backend", &[], &["reports"], true)
+        .await
+        .unwrap();
+    let first = first.unwrap();
+    Consumer::authenticate(&db, "backend", &first).await.unwrap();
+    assert!(matches!(
+        Consumer::authenticate(&db, "backend", "wrong").await,
+        Err(RustersError::InvalidCredentialsError)
+    ));
+    let second = backend.rotate_secret(&db, None).await.unwrap();
+    Consumer::authenticate(&db, "backend", &first).await.unwrap();
+    Consumer::authenticate(&db, "backend", &second).await.unwrap();
+    let third = backend.rotate_secret(&db, Some(chrono::Duration::zero())).await.unwrap();
+    assert!(Consumer::authenticate(&db, "backend", &first).await.is_err());
+    assert!(Consumer::authenticate(&db, "backend", &second).await.is_err());
+    Consumer::authenticate(&db, "backend", &third).await.unwrap();
+    let (spa, _) = Consumer::register(&db, "spa", &[], &[], false).await.unwrap();
+    assert!(Consumer::authenticate(&db, "spa", "").await.is_err());
+    backend.deactivate(&db).await.unwrap();
+    assert!(Consumer::lookup(&db, "backend").await.is_err());
+    assert!(Consumer::authenticate(&db, "backend", &third).await.is_err());
+    assert!(!Consumer::lookup_by_pk(&db, backend.get_pk()).await.unwrap().get_is_active());
+    assert!(matches!(
+        Consumer::always(&db, "backend").await,
+        Err(RustersError::InactiveConsumerError)
+    ));
+    Consumer::always(&db, "spa").await.unwrap();
+    spa.deactivate(&db).await.unwrap();
+    delete_db_file_if_exists(&db_name);
+}
+#[async_std::test]
+async fn consumers_list_rename_and_deactivate() {
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let mut alpha = Consumer::insert(&db, "alpha").await.unwrap();
+    let beta = Consumer::insert(&db, "beta").await.unwrap();
+    Consumer::insert(&db, "gamma").await.unwrap();
+    let live = Token::basic(&db, None).await.unwrap();
+    ConsumableToken::insert(&db, &live, &alpha).await.unwrap();
+    let spent = Token::basic(&db, None).await.unwrap();
+    ConsumableToken::insert(&db, &spent, &alpha).await.unwrap();
+    spent.expire(&db).await.unwrap();
+    let page = Consumer::list(&db, false, 2, 0).await.unwrap();
+    assert_eq!(
+        page.iter().map(|(c, n)| (c.get_name(), *n)).collect::<Vec<(String, i64)>>(),
+        vec![("alpha".to_string(), 1), ("beta".to_string(), 0)]
+    );
+    let page = Consumer::list(&db, false, 2, 2).await.unwrap();
+    assert_eq!(page.len(), 1);
+    assert_eq!(page[0].0.get_name(), "gamma");
+    assert!(matches!(
+        alpha.rename(&db, "beta").await,
+        Err(RustersError::ConsumerNameTakenError)
+    ));
+    alpha.rename(&db, "delta").await.unwrap();
+    assert_eq!(Consumer::lookup(&db, "delta").await.unwrap().get_pk(), alpha.get_pk());
+    alpha.deactivate(&db).await.unwrap();
+    assert!(Token::lookup_by_pk(&db, live.get_pk()).await.is_err());
+    assert_eq!(alpha.count_live_tokens(&db).await.unwrap(), 0);
+    assert_eq!(Consumer::list(&db, false, 10, 0).await.unwrap().len(), 2);
+    assert_eq!(Consumer::list(&db, true, 10, 0).await.unwrap().len(), 3);
+    let mut alpha = Consumer::lookup_by_pk(&db, alpha.get_pk()).await.unwrap();
+    let delta = Consumer::insert(&db, "delta").await.unwrap();
+    assert!(matches!(
+        alpha.reactivate(&db).await,
+        Err(RustersError::ConsumerNameTakenError)
+    ));
+    delta.deactivate(&db).await.unwrap();
+    alpha.reactivate(&db).await.unwrap();
+    assert!(alpha.get_is_active());
+    assert_eq!(Consumer::lookup(&db, "delta").await.unwrap().get_pk(), alpha.get_pk());
+    beta.deactivate(&db).await.unwrap();
+    delete_db_file_if_exists(&db_name);
+}
+#[async_std::test]
+async fn consume_token_with_payload() {
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let c = get_consumer(&db).await;
+    let other = Consumer::always(&db, "other").await.unwrap();
+    let invite = serde_json::json!({ "team": 7, "role": "editor" });
+    let t = Token::basic(&db, None).await.unwrap();
+    ConsumableToken::insert_with_json(&db, &t, &c, &invite).await.unwrap();
+    assert!(ConsumableToken::consume(&db, &t.get_hash(), &other, None).await.is_err());
+    let consumed = ConsumableToken::consume(&db, &t.get_hash(), &c, None).await.unwrap();
+    assert_eq!(consumed.get_payload_json::<serde_json::Value>().unwrap(), Some(invite));
+    assert!(matches!(
+        ConsumableToken::consume(&db, &t.get_hash(), &c, None).await,
+        Err(RustersError::TokenExhaustedError)
+    ));
+    let t = Token::basic(&db, None).await.unwrap();
+    ConsumableToken::insert_with_payload(&db, &t, &c, Some("user:42")).await.unwrap();
+    let consumed = ConsumableToken::consume(&db, &t.get_hash(), &c, None).await.unwrap();
+    assert_eq!(consumed.get_payload().as_deref(), Some("user:42"));
+    assert!(matches!(
+        consumed.get_payload_json::<serde_json::Value>(),
+        Err(RustersError::JSONError(_))
+    ));
+    let t = Token::basic(&db, None).await.unwrap();
+    ConsumableToken::insert(&db, &t, &c).await.unwrap();
+    let consumed = ConsumableToken::consume(&db, &t.get_hash(), &c, None).await.unwrap();
+    assert!(consumed.get_payload().is_none());
+    delete_db_file_if_exists(&db_name);
+}
+#[async_std::test]
+async fn consume_multi_use_token_until_exhausted() {
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let c = get_consumer(&db).await;
+    let u = get_new_user(&db).await;
+    let t = Token::basic(&db, None).await.unwrap();
+    let code = ConsumableToken::insert_with_max_uses(
+        &db, &t, &c, 3, Some("class:101")
+    ).await.unwrap();
+    assert_eq!((code.get_uses(), code.get_max_uses()), (0, 3));
+    let unusable = Token::basic(&db, None).await.unwrap();
+    assert!(matches!(
+        ConsumableToken::insert_with_max_uses(&db, &unusable, &c, 0, None).await,
+        Err(RustersError::InvalidMaxUsesError)
+    ));
+    let first = ConsumableToken::consume(&db, &t.get_hash(), &c, Some(&u)).await.unwrap();
+    assert_eq!(first.get_uses(), 1);
+    assert_eq!(first.get_payload().as_deref(), Some("class:101"));
+    ConsumableToken::consume(&db, &t.get_hash(), &c, None).await.unwrap();
+    assert!(Token::lookup_by_pk(&db, t.get_pk()).await.is_ok());
+    let last = ConsumableToken::consume(&db, &t.get_hash(), &c, Some(&u)).await.unwrap();
+    assert_eq!(last.get_uses(), 3);
+    assert!(Token::lookup_by_pk(&db, t.get_pk()).await.is_err());
+    assert!(matches!(
+        ConsumableToken::consume(&db, &t.get_hash(), &c, Some(&u)).await,
+        Err(RustersError::TokenExhaustedError)
+    ));
+    let uses = last.lookup_uses(&db).await.unwrap();
+    assert_eq!(
+        uses.iter().map(|u| u.get_user_pk()).collect::<Vec<Option<i64>>>(),
+        vec![Some(u.get_pk()), None, Some(u.get_pk())]
+    );
+    let expired = Token::basic(&db, None).await.unwrap();
+    ConsumableToken::insert_with_max_uses(&db, &expired, &c, 5, None).await.unwrap();
+    let hash = expired.get_hash();
+    expired.expire(&db).await.unwrap();
+    assert!(matches!(
+        ConsumableToken::consume(&db, &hash, &c, None).await,
+        Err(RustersError::InvalidTokenError)
+    ));
+    delete_db_file_if_exists(&db_name);
+}
+#[cfg(feature = "jwt")]
+#[test]
+fn jwt_keys_match_published_vectors() {
+    use crate::JwtKey;
+    // HS256 example from jwt.io
+    let hs = JwtKey::hs256("hs", b"your-256-bit-secret".to_vec());
+    let input = "eyJhbGciOiJIUzI1NiIsInR5cCI6IkpXVCJ9.\
+        eyJzdWIiOiIxMjM0NTY3ODkwIiwibmFtZSI6IkpvaG4gRG9lIiwiaWF0IjoxNTE2MjM5MDIyfQ";
+    let sig = base64::decode_config(
+        "SflKxwRJSMeKKF2QT4fwpMeJf36POk6yJV_adQssw5c", base64::URL_SAFE_NO_PAD
+    ).unwrap();
+    assert_eq!(hs.sign(input.as_bytes()).unwrap(), sig);
+    assert!(hs.verify(input.as_bytes(), &sig));
+    // Ed25519 example from RFC 8037 appendix A
+    let d = base64::decode_config(
+        "nWGxne_9WmC6hEr0kuwsxERJxWl7MmkZcDusAxyuf2A", base64::URL_SAFE_NO_PAD
+    ).unwrap();
+    let ed = JwtKey::ed25519("ed", &d.try_into().unwrap());
+    assert_eq!(
+        ed.public_jwk().unwrap()["x"],
+        "11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo"
+    );
+    let input = "eyJhbGciOiJFZERTQSJ9.RXhhbXBsZSBvZiBFZDI1NTE5IHNpZ25pbmc";
+    let sig = base64::decode_config(
+        "hgyY0il_MGCjP0JzlnLWG1PPOt7-09PGcvMg3AIbQR6dWbhijcNR4ki4iylGjg5BhVsPt9g7sVvpAr_MuM0KAg",
+        base64::URL_SAFE_NO_PAD
+    ).unwrap();
+    assert_eq!(ed.sign(input.as_bytes()).unwrap(), sig);
+    assert!(ed.verify(input.as_bytes(), &sig));
+}
+#[cfg(feature = "jwt")]
+#[async_std::test]
+async fn jwt_issue_and_validate() {
+    use crate::{ Jwt, JwtConfig, JwtKey, };
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let u = get_new_user(&db).await;
+    let t = Token::basic(&db, None).await.unwrap();
+    let s = get_session(&db, &t).await;
+    let config = JwtConfig::new("https://auth.example.com", "api");
+    let keys = vec![
+        JwtKey::hs256("hs", b"a shared secret".to_vec()),
+        JwtKey::generate_ed25519("ed"),
+    ];
+    for key in &keys {
+        let jwt = Jwt::issue(&config, key, &u, Some(&s), None).unwrap();
+        let v = Jwt::validate(&config, &keys, &jwt).unwrap();
+        assert_eq!(v.get_subject(), u.get_pk().to_string());
+        assert_eq!(v.get_session_pk(), Some(s.get_pk()));
+        assert_eq!(v.get_key_id(), key.get_kid());
+        let other_aud = JwtConfig::new("https://auth.example.com", "other");
+        assert!(Jwt::validate(&other_aud, &keys, &jwt).is_err());
+        let other_iss = JwtConfig::new("https://evil.example.com", "api");
+        assert!(Jwt::validate(&other_iss, &keys, &jwt).is_err());
+    }
+    let expired = Jwt::issue(
+        &config, &keys[0], &u, None, Some(chrono::Duration::minutes(-5))
+    ).unwrap();
+    assert!(Jwt::validate(&config, &keys, &expired).is_err());
+    // an EdDSA token relabelled as HS256 must not verify
+    let ed_jwt = Jwt::issue(&config, &keys[1], &u, None, None).unwrap();
+    let forged_header = base64::encode_config(
+        r#"{"alg":"HS256","kid":"ed"}"#, base64::URL_SAFE_NO_PAD
+    );
+    let forged = format!(
+        "{}.{}",
+        forged_header,
+        ed_jwt.split_once('.').unwrap().1
+    );
+    assert!(Jwt::validate(&config, &keys, &forged).is_err());
+    let jwks = Jwt::jwks(&keys);
+    assert_eq!(jwks["keys"].as_array().unwrap().len(), 1);
+    assert_eq!(jwks["keys"][0]["kid"], "ed");
+    delete_db_file_if_exists(&db_name);
+}
+#[cfg(feature = "jwt")]
+#[async_std::test]
+async fn oidc_id_tokens_and_userinfo() {
+    use crate::{
+        AuthorizationRequest,
+        JwtConfig,
+        JwtKey,
+        OAuthServer,
+        OidcProvider,
+        TokenRequest,
+    };
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let mut u = get_new_user(&db).await;
+    u.set_email(&db, "test@example.com").await.unwrap();
+    let key = JwtKey::generate_ed25519("oidc");
+    let provider = OidcProvider::new("https://auth.example", key.clone());
+    let discovery = provider.discovery();
+    assert_eq!(discovery["issuer"], "https://auth.example");
+    assert_eq!(discovery["userinfo_endpoint"], "https://auth.example/userinfo");
+    assert_eq!(discovery["id_token_signing_alg_values_supported"][0], "EdDSA");
+    assert_eq!(provider.jwks()["keys"][0]["kid"], "oidc");
+    let server = OAuthServer { oidc: Some(provider.clone()), ..OAuthServer::new() };
+    let redirect = "https://spa.example/callback";
+    Consumer::register(&db, "spa", &[redirect], &[], false).await.unwrap();
+    let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+    let mut authorize = AuthorizationRequest {
+        response_type: "code".to_string(),
+        client_id: "spa".to_string(),
+        scope: Some("openid email".to_string()),
+        code_challenge: Some(crate::token::oauth::pkce_challenge(verifier)),
+        code_challenge_method: Some("S256".to_string()),
+        nonce: Some("n-0S6_WzA2Mj".to_string()),
+        ..Default::default()
+    };
+    let mut exchange = TokenRequest {
+        grant_type: "authorization_code".to_string(),
+        client_id: Some("spa".to_string()),
+        code_verifier: Some(verifier.to_string()),
+        ..Default::default()
+    };
+    exchange.code = Some(server.authorize(&db, &u, &authorize).await.unwrap().get_code());
+    let issued = server.token(&db, &exchange).await.unwrap();
+    let id_token = issued.id_token.unwrap();
+    let config = JwtConfig::new("https://auth.example", "spa");
+    let validated = crate::Jwt::validate(&config, std::slice::from_ref(&key), &id_token).unwrap();
+    assert_eq!(validated.get_subject(), u.get_pk().to_string());
+    assert!(validated.get_username().is_none());
+    let payload: serde_json::Value = serde_json::from_slice(&base64::decode_config(
+        id_token.split('.').nth(1).unwrap(), base64::URL_SAFE_NO_PAD
+    ).unwrap()).unwrap();
+    assert_eq!(payload["nonce"], "n-0S6_WzA2Mj");
+    assert_eq!(payload["email"], "test@example.com");
+    assert_eq!(payload["email_verified"], false);
+    let info = provider.userinfo(&db, &issued.access_token).await.unwrap();
+    assert_eq!(info.sub, u.get_pk().to_string());
+    assert_eq!(info.email.as_deref(), Some("test@example.com"));
+    assert!(info.preferred_username.is_none());
+    assert!(provider.userinfo(&db, &issued.refresh_token.unwrap()).await.is_err());
+    authorize.scope = Some("profile".to_string());
+    authorize.nonce = None;
+    exchange.code = Some(server.authorize(&db, &u, &authorize).await.unwrap().get_code());
+    let issued = server.token(&db, &exchange).await.unwrap();
+    assert!(issued.id_token.is_none());
+    assert!(matches!(
+        provider.userinfo(&db, &issued.access_token).await,
+        Err(RustersError::InsufficientScope(_))
+    ));
+    delete_db_file_if_exists(&db_name);
+}
+#[cfg(feature = "paseto")]
+fn from_hex<const N: usize>(hex: &str) -> [u8; N] {
+    (0..N).map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap())
+        .collect::<Vec<u8>>()
+        .try_into()
+        .unwrap()
+}
+#[cfg(feature = "paseto")]
+#[test]
+fn paseto_v4_test_vectors() {
+    use crate::token::paseto;
+    // 4-E-1 from the PASETO test vectors
+    let key = from_hex::<32>(
+        "707172737475767778797a7b7c7d7e7f808182838485868788898a8b8c8d8e8f"
+    );
+    let secret = br#"{"data":"this is a secret message","exp":"2022-01-01T00:00:00+00:00"}"#;
+    let local = "v4.local.AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAQAr68PS4AXe7If_\
+        ZgesdkUMvSwscFlAl1pk5HC0e8kApeaqMfGo_7OpBnwJOAbY9V7WU6abu74MmcUE8YWAiaArVI8X\
+        J5hOb_4v9RmDkneN0S92dx0OW4pgy7omxgf3S8c3LlQg";
+    assert_eq!(
+        paseto::encrypt_with_nonce(&key, &[0; 32], secret, b"", b"").unwrap(),
+        local
+    );
+    assert_eq!(paseto::decrypt(&key, local, b"").unwrap().0, secret.to_vec());
+    assert!(paseto::decrypt(&key, local, b"implicit").is_err());
+    // 4-S-1, 4-S-2 and 4-S-3 from the PASETO test vectors
+    let signing = ed25519_dalek::SigningKey::from_bytes(&from_hex::<32>(
+        "b4cbfb43df4ce210727d953e4a713307fa19bb7d9f85041438d9e11b942a3774"
+    ));
+    assert_eq!(
+        signing.verifying_key().to_bytes(),
+        from_hex::<32>("1eb9dbbbbc047c03fd70604e0071f0987e16b28b757225c11f00415d0e20b1a2")
+    );
+    let signed = br#"{"data":"this is a signed message","exp":"2022-01-01T00:00:00+00:00"}"#;
+    let footer = br#"{"kid":"zVhMiPBP9fRf2snEcT7gFTioeA9COcNy9DfgL1W60haN"}"#;
+    let implicit = br#"{"test-vector":"4-S-3"}"#;
+    let vectors: [(&[u8], &[u8], &str); 3] = [
+        (
+            b"",
+            b"",
+            "v4.public.eyJkYXRhIjoidGhpcyBpcyBhIHNpZ25lZCBtZXNzYWdlIiwiZXhwIjoiMjAy\
+            Mi0wMS0wMVQwMDowMDowMCswMDowMCJ9bg_XBBzds8lTZShVlwwKSgeKpLT3yukTw6JUz3W4h_\
+            ExsQV-P0V54zemZDcAxFaSeef1QlXEFtkqxT1ciiQEDA",
+        ),
+        (
+            footer,
+            b"",
+            "v4.public.eyJkYXRhIjoidGhpcyBpcyBhIHNpZ25lZCBtZXNzYWdlIiwiZXhwIjoiMjAy\
+            Mi0wMS0wMVQwMDowMDowMCswMDowMCJ9v3Jt8mx_TdM2ceTGoqwrh4yDFn0XsHvvV_D0DtwQxV\
+            rJEBMl0F2caAdgnpKlt4p7xBnx1HcO-SPo8FPp214HDw.eyJraWQiOiJ6VmhNaVBCUDlmUmYy\
+            c25FY1Q3Z0ZUaW9lQTlDT2NOeTlEZmdMMVc2MGhhTiJ9",
+        ),
+        (
+            footer,
+            implicit,
+            "v4.public.eyJkYXRhIjoidGhpcyBpcyBhIHNpZ25lZCBtZXNzYWdlIiwiZXhwIjoiMjAy\
+            Mi0wMS0wMVQwMDowMDowMCswMDowMCJ9NPWciuD3d0o5eXJXG5pJy-DiVEoyPYWs1YSTwWHNJq\
+            6DZD3je5gf-0M4JR9ipdUSJbIovzmBECeaWmaqcaP0DQ.eyJraWQiOiJ6VmhNaVBCUDlmUmYy\
+            c25FY1Q3Z0ZUaW9lQTlDT2NOeTlEZmdMMVc2MGhhTiJ9",
+        ),
+    ];
+    for (f, i, token) in vectors {
+        assert_eq!(paseto::sign(&signing, signed, f, i), token);
+        let (m, vf) = paseto::verify(&signing.verifying_key(), token, i).unwrap();
+        assert_eq!(m, signed.to_vec());
+        assert_eq!(vf, f.to_vec());
+    }
+}
+#[cfg(feature = "paseto")]
+#[async_std::test]
+async fn paseto_issue_and_validate() {
+    use crate::{ Paseto, PasetoKey, };
+    let db_name = get_file_name();
+    create_db_file_if_not_exist(&db_name);
+    let db = get_db(&db_name).await;
+    RustersMigrator::migrate(&db).await.unwrap();
+    let u = get_new_user(&db).await;
+    let t = Token::basic(&db, None).await.unwrap();
+    let s = get_session(&db, &t).await;
+    let local = PasetoKey::generate_local("local");
+    let public = PasetoKey::generate_public("public");
+    let keys = vec![local.clone(), public.clone()];
+    for key in &keys {
+        let token = Paseto::issue(key, &u, Some(&s), None).unwrap();
+        assert!(token.starts_with(key.get_header()));
+        let v = Paseto::validate(&keys, &token).unwrap();
+        assert_eq!(v.get_subject(), u.get_pk().to_string());
+        assert_eq!(v.get_session_pk(), Some(s.get_pk()));
+        assert_eq!(v.get_key_id(), key.get_kid());
+    }
+    let verifying = vec![public.verifying().unwrap()];
+    let signed = Paseto::issue(&public, &u, None, None).unwrap();
+    Paseto::validate(&verifying, &signed).unwrap();
+    assert!(Paseto::issue(&verifying[0], &u, None, None).is_err());
+    let expired = Paseto::issue(
+        &local, &u, None, Some(chrono::Duration::seconds(-1))
+    ).unwrap();
+    assert!(Paseto::validate(&keys, &expired).is_err());
+    let other = vec![PasetoKey::generate_local("local")];
+    let encrypted = Paseto::issue(&local, &u, None, None).unwrap();
+    assert!(Paseto::validate(&other, &encrypted).is_err());
+    delete_db_file_if_exists(&db_name);
+}
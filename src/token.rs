@@ -1,4 +1,12 @@
 pub mod consumable_token;
+pub mod introspection;
+#[cfg(feature = "jwt")]
+pub mod jwt;
+pub mod oauth;
+#[cfg(feature = "paseto")]
+pub mod paseto;
+pub mod refresh_token;
+pub mod signed_token;
 use {
     chrono::{
         DateTime,
@@ -84,6 +92,85 @@ impl Token {
             .last_insert_rowid();
         Self::lookup_by_pk(db, pk).await
     }
+    /// Inserts a token which only grants `scopes`
+    pub async fn insert_with_scopes<'a>(
+        db: &SqlitePool,
+        hash: impl Hash,
+        expires: Option<Duration>,
+        scopes: &[&'a str],
+    ) -> Result<Self, RustersError> {
+        let token = Self::insert(db, hash, expires).await?;
+        for scope in scopes {
+            token.add_scope(db, scope).await?;
+        }
+        Ok(token)
+    }
+    pub async fn add_scope<'a>(
+        &self, db: &SqlitePool, scope: &'a str
+    ) -> Result<(), RustersError> {
+        query("
+            insert or ignore into TokenScopes (
+                token_pk,
+                scope,
+                created_dt
+            ) values (
+                $1,
+                $2,
+                $3
+            )"
+        ).bind(self.get_pk())
+            .bind(scope)
+            .bind(Utc::now())
+            .execute(db)
+            .await
+            .quick_match()?;
+        Ok(())
+    }
+    pub async fn lookup_scopes(
+        &self, db: &SqlitePool
+    ) -> Result<Vec<String>, RustersError> {
+        let scopes = query_as::<_, (String,)>("
+            select scope
+            from TokenScopes
+            where token_pk = $1
+            order by scope"
+        ).bind(self.get_pk())
+            .fetch_all(db)
+            .await
+            .quick_match()?;
+        Ok(scopes.into_iter().map(|s| s.0).collect())
+    }
+    pub async fn has_scope<'a>(
+        &self, db: &SqlitePool, scope: &'a str
+    ) -> Result<bool, RustersError> {
+        let count = query_as::<_, (i64,)>("
+            select count(*)
+            from TokenScopes
+            where token_pk = $1
+            and scope = $2"
+        ).bind(self.get_pk())
+            .bind(scope)
+            .fetch_one(db)
+            .await
+            .quick_match()?.0;
+        Ok(count > 0)
+    }
+    /// Fails with `InsufficientScope`, listing what is missing, unless the
+    /// token was granted every one of `scopes`
+    pub async fn require_scopes<'a>(
+        &self, db: &SqlitePool, scopes: &[&'a str]
+    ) -> Result<(), RustersError> {
+        let granted = self.lookup_scopes(db).await?;
+        let missing = scopes.iter()
+            .filter(|s| !granted.iter().any(|g| g == *s))
+            .map(|s| s.to_string())
+            .collect::<Vec<String>>();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(RustersError::InsufficientScope(missing))
+        }
+    }
     pub async fn basic(
         db: &SqlitePool, expires: Option<Duration>
     ) -> Result<Self, RustersError> {
@@ -1,9 +1,11 @@
+pub mod consumable_token_use;
 pub mod consumer;
 use {
     chrono::{
         DateTime,
         Utc,
     },
+    consumable_token_use::ConsumableTokenUse,
     consumer::Consumer,
     crate::{
         error::{
@@ -11,14 +13,25 @@ use {
             RustersError,
         },
         token::Token,
+        user::User,
+    },
+    serde::{
+        de::DeserializeOwned,
+        Serialize,
     },
     sqlx::{ FromRow, SqlitePool, query, query_as, },
 };
+/// A `Token` meant for one `Consumer`, optionally carrying a payload for the
+/// flow it belongs to, such as the team and role of an invite. It can be
+/// consumed `max_uses` times, once unless inserted otherwise
 #[derive(FromRow)]
 pub struct ConsumableToken {
     pk: i64,
     token_pk: i64,
     consumer_pk: i64,
+    payload: Option<String>,
+    max_uses: i64,
+    uses: i64,
     created_dt: DateTime<Utc>,
 }
 impl ConsumableToken {
@@ -31,6 +44,23 @@ impl ConsumableToken {
     pub fn get_consumer_pk(&self) -> i64 {
         self.consumer_pk
     }
+    pub fn get_payload(&self) -> Option<String> {
+        self.payload.clone()
+    }
+    /// The payload parsed as JSON, failing with `JSONError` when it is not a
+    /// `T`
+    pub fn get_payload_json<T: DeserializeOwned>(&self) -> Result<Option<T>, RustersError> {
+        match &self.payload {
+            Some(payload) => serde_json::from_str(payload).map(Some).quick_match(),
+            None => Ok(None),
+        }
+    }
+    pub fn get_max_uses(&self) -> i64 {
+        self.max_uses
+    }
+    pub fn get_uses(&self) -> i64 {
+        self.uses
+    }
     pub fn get_created_dt(&self) -> DateTime<Utc> {
         self.created_dt
     }
@@ -42,6 +72,9 @@ impl ConsumableToken {
                 pk,
                 token_pk,
                 consumer_pk,
+                payload,
+                max_uses,
+                uses,
                 created_dt
             from ConsumableTokens
             where pk = $1"
@@ -58,6 +91,9 @@ impl ConsumableToken {
                 pk,
                 token_pk,
                 consumer_pk,
+                payload,
+                max_uses,
+                uses,
                 created_dt
             from ConsumableTokens
             where token_pk = $1
@@ -71,18 +107,47 @@ impl ConsumableToken {
     pub async fn insert(
         db: &SqlitePool, token: &Token, consumer: &Consumer
     ) -> Result<Self, RustersError> {
+        Self::insert_with_payload(db, token, consumer, None).await
+    }
+    /// Inserts a token carrying an opaque `payload`
+    pub async fn insert_with_payload<'a>(
+        db: &SqlitePool, token: &Token, consumer: &Consumer, payload: Option<&'a str>
+    ) -> Result<Self, RustersError> {
+        Self::insert_with_max_uses(db, token, consumer, 1, payload).await
+    }
+    /// Inserts a token which can be consumed `max_uses` times before it is
+    /// exhausted, failing with `InvalidMaxUsesError` when that is below one
+    pub async fn insert_with_max_uses<'a>(
+        db: &SqlitePool,
+        token: &Token,
+        consumer: &Consumer,
+        max_uses: i64,
+        payload: Option<&'a str>,
+    ) -> Result<Self, RustersError> {
+        if max_uses < 1 {
+            return Err(RustersError::InvalidMaxUsesError);
+        }
         let pk = query("
             insert into ConsumableTokens (
                 token_pk,
                 consumer_pk,
+                payload,
+                max_uses,
+                uses,
                 created_dt
             ) values (
                 $1,
                 $2,
-                $3
+                $3,
+                $4,
+                $5,
+                $6
             )"
         ).bind(token.get_pk())
             .bind(consumer.get_pk())
+            .bind(payload)
+            .bind(max_uses)
+            .bind(0_i64)
             .bind(Utc::now())
             .execute(db)
             .await
@@ -90,4 +155,87 @@ impl ConsumableToken {
             .last_insert_rowid();
         Self::lookup_by_pk(db, pk).await
     }
+    /// Inserts a token carrying `payload` serialized as JSON
+    pub async fn insert_with_json(
+        db: &SqlitePool, token: &Token, consumer: &Consumer, payload: &impl Serialize
+    ) -> Result<Self, RustersError> {
+        let json = serde_json::to_string(payload).quick_match()?;
+        Self::insert_with_payload(db, token, consumer, Some(&json)).await
+    }
+    /// Consumes one use of the unexpired token presented as `hash` for
+    /// `consumer`, recording who used it, and returns it with its payload. The
+    /// token expires with its last use; consuming it after that fails with
+    /// `TokenExhaustedError`. The count, the record and the expiry are
+    /// committed together
+    pub async fn consume<'a>(
+        db: &SqlitePool, hash: &'a str, consumer: &Consumer, used_by: Option<&User>
+    ) -> Result<Self, RustersError> {
+        let now = Utc::now();
+        let mut tx = db.begin().await.quick_match()?;
+        let counted = query("
+            update ConsumableTokens
+            set uses = uses + 1
+            where consumer_pk = $1
+            and uses < max_uses
+            and token_pk in (
+                select pk
+                from Tokens
+                where hash = $2
+                and expired_dt > $3
+            )"
+        ).bind(consumer.get_pk())
+            .bind(hash)
+            .bind(now)
+            .execute(&mut tx)
+            .await
+            .quick_match()?
+            .rows_affected() > 0;
+        let consumable = query_as::<_, Self>("
+            select
+                c.pk,
+                c.token_pk,
+                c.consumer_pk,
+                c.payload,
+                c.max_uses,
+                c.uses,
+                c.created_dt
+            from ConsumableTokens as c
+            join Tokens as t
+            on t.pk = c.token_pk
+            where t.hash = $1
+            and c.consumer_pk = $2"
+        ).bind(hash)
+            .bind(consumer.get_pk())
+            .fetch_optional(&mut tx)
+            .await
+            .quick_match()?
+            .ok_or(RustersError::InvalidTokenError)?;
+        if !counted {
+            return Err(match consumable.uses >= consumable.max_uses {
+                true => RustersError::TokenExhaustedError,
+                false => RustersError::InvalidTokenError,
+            });
+        }
+        ConsumableTokenUse::insert(&mut tx, &consumable, used_by).await?;
+        if consumable.uses >= consumable.max_uses {
+            query("
+                update Tokens
+                set expired_dt = $1
+                where pk = $2
+                and expired_dt > $1"
+            ).bind(now)
+                .bind(consumable.token_pk)
+                .execute(&mut tx)
+                .await
+                .quick_match()?;
+        }
+        tx.commit().await.quick_match()?;
+        Ok(consumable)
+    }
+    /// Every use of the token, oldest first
+    pub async fn lookup_uses(
+        &self, db: &SqlitePool
+    ) -> Result<Vec<ConsumableTokenUse>, RustersError> {
+        ConsumableTokenUse::lookup_all(db, self).await
+    }
 }
@@ -0,0 +1,78 @@
+use {
+    chrono::{
+        DateTime,
+        Utc,
+    },
+    crate::{
+        error::{
+            MatchRustersError,
+            RustersError,
+        },
+        token::consumable_token::ConsumableToken,
+        user::User,
+    },
+    sqlx::{ FromRow, Sqlite, SqlitePool, Transaction, query, query_as, },
+};
+/// One use of a `ConsumableToken`, by a `User` when the flow knows who
+#[derive(FromRow)]
+pub struct ConsumableTokenUse {
+    pk: i64,
+    consumable_token_pk: i64,
+    user_pk: Option<i64>,
+    used_dt: DateTime<Utc>,
+}
+impl ConsumableTokenUse {
+    pub fn get_pk(&self) -> i64 {
+        self.pk
+    }
+    pub fn get_consumable_token_pk(&self) -> i64 {
+        self.consumable_token_pk
+    }
+    pub fn get_user_pk(&self) -> Option<i64> {
+        self.user_pk
+    }
+    pub fn get_used_dt(&self) -> DateTime<Utc> {
+        self.used_dt
+    }
+    pub async fn lookup_all(
+        db: &SqlitePool, consumable: &ConsumableToken
+    ) -> Result<Vec<Self>, RustersError> {
+        query_as::<_, Self>("
+            select
+                pk,
+                consumable_token_pk,
+                user_pk,
+                used_dt
+            from ConsumableTokenUses
+            where consumable_token_pk = $1
+            order by pk"
+        ).bind(consumable.get_pk())
+            .fetch_all(db)
+            .await
+            .quick_match()
+    }
+    /// Records a use inside the transaction that counted it
+    pub(crate) async fn insert(
+        tx: &mut Transaction<'_, Sqlite>,
+        consumable: &ConsumableToken,
+        user: Option<&User>,
+    ) -> Result<(), RustersError> {
+        query("
+            insert into ConsumableTokenUses (
+                consumable_token_pk,
+                user_pk,
+                used_dt
+            ) values (
+                $1,
+                $2,
+                $3
+            )"
+        ).bind(consumable.get_pk())
+            .bind(user.map(|u| u.get_pk()))
+            .bind(Utc::now())
+            .execute(&mut *tx)
+            .await
+            .quick_match()?;
+        Ok(())
+    }
+}
@@ -1,18 +1,33 @@
 use {
     chrono::{
         DateTime,
+        Duration,
         Utc,
     },
-    crate::error::{
-        MatchRustersError,
-        RustersError,
+    crate::{
+        error::{
+            MatchRustersError,
+            RustersError,
+        },
+        hash::{
+            Basic,
+            Hash,
+            Secure,
+        },
     },
-    sqlx::{ FromRow, SqlitePool, query, query_as, },
+    sqlx::{ FromRow, Row, SqlitePool, query, query_as, },
 };
+/// A named client of tokens. Consumers registered as OAuth clients also have
+/// redirect URIs and, when confidential, a `Secure` hash of their secret; the
+/// name doubles as the `client_id`. After a rotation the previous secret keeps
+/// authenticating until `previous_secret_expired_dt`
 #[derive(FromRow)]
 pub struct Consumer {
     pk: i64,
     name: String,
+    secret_hash: Option<String>,
+    previous_secret_hash: Option<String>,
+    previous_secret_expired_dt: Option<DateTime<Utc>>,
     is_active: bool,
     created_dt: DateTime<Utc>,
 }
@@ -23,6 +38,13 @@ impl Consumer {
     pub fn get_name(&self) -> String {
         self.name.clone()
     }
+    /// Whether the consumer authenticates with a client secret
+    pub fn get_is_confidential(&self) -> bool {
+        self.secret_hash.is_some()
+    }
+    pub fn get_previous_secret_expired_dt(&self) -> Option<DateTime<Utc>> {
+        self.previous_secret_expired_dt
+    }
     pub fn get_is_active(&self) -> bool {
         self.is_active
     }
@@ -36,6 +58,9 @@ impl Consumer {
             select
                 pk,
                 name,
+                secret_hash,
+                previous_secret_hash,
+                previous_secret_expired_dt,
                 is_active,
                 created_dt
             from Consumers
@@ -45,6 +70,7 @@ impl Consumer {
             .await
             .quick_match()
     }
+    /// The active consumer named `name`
     pub async fn lookup<'a>(
         db: &SqlitePool, name: &'a str
     ) -> Result<Self, RustersError> {
@@ -52,10 +78,14 @@ impl Consumer {
             select
                 pk,
                 name,
+                secret_hash,
+                previous_secret_hash,
+                previous_secret_expired_dt,
                 is_active,
                 created_dt
             from Consumers
-            where name = $1"
+            where name = $1
+            and is_active = 1"
         ).bind(name)
             .fetch_one(db)
             .await
@@ -83,13 +113,338 @@ impl Consumer {
             .last_insert_rowid();
         Self::lookup_by_pk(db, pk).await
     }
+    /// The active consumer named `name`, inserted if no consumer by that name
+    /// was ever inserted. A deactivated consumer is not replaced
     pub async fn always(
         db: &SqlitePool, name: impl AsRef<str>
     ) -> Result<Self, RustersError> {
         let n = name.as_ref();
-        match Self::lookup(db, n).await {
-            Ok(c) => Ok(c),
-            Err(_) => Self::insert(db, n).await
+        if let Ok(c) = Self::lookup(db, n).await {
+            return Ok(c);
+        }
+        let deactivated = query_as::<_, (i64,)>("
+            select count(*)
+            from Consumers
+            where name = $1
+            and is_active = 0"
+        ).bind(n)
+            .fetch_one(db)
+            .await
+            .quick_match()?.0 > 0;
+        if deactivated {
+            return Err(RustersError::InactiveConsumerError);
+        }
+        Self::insert(db, n).await
+    }
+    /// Registers an OAuth client allowed to redirect to `redirect_uris` and to
+    /// request `scopes` for itself. A confidential client is given a secret,
+    /// returned raw only here
+    pub async fn register<'a>(
+        db: &SqlitePool,
+        name: &'a str,
+        redirect_uris: &[&'a str],
+        scopes: &[&'a str],
+        confidential: bool,
+    ) -> Result<(Self, Option<String>), RustersError> {
+        let consumer = Self::insert(db, name).await?;
+        for uri in redirect_uris {
+            consumer.add_redirect_uri(db, uri).await?;
+        }
+        for scope in scopes {
+            consumer.add_scope(db, scope).await?;
+        }
+        if !confidential {
+            return Ok((consumer, None));
+        }
+        let secret = consumer.rotate_secret(db, None).await?;
+        Ok((Self::lookup_by_pk(db, consumer.pk).await?, Some(secret)))
+    }
+    /// How long a replaced secret keeps authenticating
+    pub fn default_secret_overlap() -> Duration {
+        Duration::days(1)
+    }
+    /// Gives the consumer a new secret, returned raw only here. The current
+    /// secret keeps authenticating for `overlap` so clients can be redeployed
+    pub async fn rotate_secret(
+        &self, db: &SqlitePool, overlap: Option<Duration>
+    ) -> Result<String, RustersError> {
+        let secret = Basic::rand()?.get_hash();
+        query("
+            update Consumers
+            set previous_secret_hash = secret_hash,
+                previous_secret_expired_dt = $1,
+                secret_hash = $2
+            where pk = $3"
+        ).bind(Utc::now() + overlap.unwrap_or_else(Self::default_secret_overlap))
+            .bind(Secure::from_string(&secret)?.get_hash())
+            .bind(self.pk)
+            .execute(db)
+            .await
+            .quick_match()?;
+        Ok(secret)
+    }
+    /// Deactivates the consumer, after which it is no longer looked up or
+    /// authenticated, and expires every outstanding token issued to it
+    pub async fn deactivate(&self, db: &SqlitePool) -> Result<(), RustersError> {
+        let now = Utc::now();
+        let mut tx = db.begin().await.quick_match()?;
+        query("
+            update Tokens
+            set expired_dt = $1
+            where expired_dt > $1
+            and pk in (
+                select token_pk
+                from ConsumableTokens
+                where consumer_pk = $2
+                union
+                select token_pk
+                from OAuthTokens
+                where consumer_pk = $2
+            )"
+        ).bind(now)
+            .bind(self.pk)
+            .execute(&mut tx)
+            .await
+            .quick_match()?;
+        query("
+            update Consumers
+            set is_active = 0
+            where pk = $1"
+        ).bind(self.pk)
+            .execute(&mut tx)
+            .await
+            .quick_match()?;
+        tx.commit().await.quick_match()
+    }
+    /// Reactivates a deactivated consumer, failing with
+    /// `ConsumerNameTakenError` when another active consumer took its name in
+    /// the meantime. Tokens expired by the deactivation stay expired
+    pub async fn reactivate(&mut self, db: &SqlitePool) -> Result<(), RustersError> {
+        self.update_name(db, &self.name.clone(), true).await?;
+        self.is_active = true;
+        Ok(())
+    }
+    /// Renames the consumer, failing with `ConsumerNameTakenError` when an
+    /// active consumer already has `name`
+    pub async fn rename<'a>(
+        &mut self, db: &SqlitePool, name: &'a str
+    ) -> Result<(), RustersError> {
+        self.update_name(db, name, false).await?;
+        self.name = name.to_string();
+        Ok(())
+    }
+    /// Sets the name, and activates the consumer when `activate`, unless that
+    /// would leave two active consumers with `name`. The check is part of the
+    /// update rather than left to `ConsumersUniqueName`, which only backs it:
+    /// sqlx replays a statement that failed on the index the next time its
+    /// connection steps it, so a refused rename could land later
+    async fn update_name<'a>(
+        &self, db: &SqlitePool, name: &'a str, activate: bool
+    ) -> Result<(), RustersError> {
+        let updated = query("
+            update Consumers
+            set name = $1,
+                is_active = max(is_active, $3)
+            where pk = $2
+            and (
+                (is_active = 0 and $3 = 0)
+                or not exists (
+                    select pk
+                    from Consumers
+                    where name = $1
+                    and is_active = 1
+                    and pk != $2
+                )
+            )"
+        ).bind(name)
+            .bind(self.pk)
+            .bind(activate)
+            .execute(db)
+            .await
+            .quick_match()?
+            .rows_affected() > 0;
+        if updated {
+            return Ok(());
+        }
+        Self::lookup_by_pk(db, self.pk).await?;
+        Err(RustersError::ConsumerNameTakenError)
+    }
+    /// Counts the unexpired tokens issued to the consumer
+    pub async fn count_live_tokens(&self, db: &SqlitePool) -> Result<i64, RustersError> {
+        Ok(query_as::<_, (i64,)>("
+            select count(*)
+            from Tokens
+            where expired_dt > $1
+            and pk in (
+                select token_pk
+                from ConsumableTokens
+                where consumer_pk = $2
+                union
+                select token_pk
+                from OAuthTokens
+                where consumer_pk = $2
+            )"
+        ).bind(Utc::now())
+            .bind(self.pk)
+            .fetch_one(db)
+            .await
+            .quick_match()?.0)
+    }
+    /// A page of consumers ordered by name, each with its count of live tokens
+    pub async fn list(
+        db: &SqlitePool, include_inactive: bool, limit: i64, offset: i64
+    ) -> Result<Vec<(Self, i64)>, RustersError> {
+        let rows = query("
+            select
+                c.pk,
+                c.name,
+                c.secret_hash,
+                c.previous_secret_hash,
+                c.previous_secret_expired_dt,
+                c.is_active,
+                c.created_dt,
+                (
+                    select count(*)
+                    from Tokens
+                    where expired_dt > $4
+                    and pk in (
+                        select token_pk
+                        from ConsumableTokens
+                        where consumer_pk = c.pk
+                        union
+                        select token_pk
+                        from OAuthTokens
+                        where consumer_pk = c.pk
+                    )
+                ) as live_tokens
+            from Consumers as c
+            where c.is_active = 1
+            or $1
+            order by c.name, c.pk
+            limit $2
+            offset $3"
+        ).bind(include_inactive)
+            .bind(limit)
+            .bind(offset)
+            .bind(Utc::now())
+            .fetch_all(db)
+            .await
+            .quick_match()?;
+        rows.iter()
+            .map(|row| Ok((Self::from_row(row)?, row.try_get("live_tokens")?)))
+            .collect::<Result<Vec<(Self, i64)>, sqlx::Error>>()
+            .quick_match()
+    }
+    pub async fn add_redirect_uri<'a>(
+        &self, db: &SqlitePool, redirect_uri: &'a str
+    ) -> Result<(), RustersError> {
+        query("
+            insert or ignore into ConsumerRedirectUris (
+                consumer_pk,
+                redirect_uri,
+                created_dt
+            ) values (
+                $1,
+                $2,
+                $3
+            )"
+        ).bind(self.pk)
+            .bind(redirect_uri)
+            .bind(Utc::now())
+            .execute(db)
+            .await
+            .quick_match()?;
+        Ok(())
+    }
+    pub async fn lookup_redirect_uris(
+        &self, db: &SqlitePool
+    ) -> Result<Vec<String>, RustersError> {
+        let uris = query_as::<_, (String,)>("
+            select redirect_uri
+            from ConsumerRedirectUris
+            where consumer_pk = $1
+            order by pk"
+        ).bind(self.pk)
+            .fetch_all(db)
+            .await
+            .quick_match()?;
+        Ok(uris.into_iter().map(|u| u.0).collect())
+    }
+    /// Allows the client to request `scope` for itself
+    pub async fn add_scope<'a>(
+        &self, db: &SqlitePool, scope: &'a str
+    ) -> Result<(), RustersError> {
+        query("
+            insert or ignore into ConsumerScopes (
+                consumer_pk,
+                scope,
+                created_dt
+            ) values (
+                $1,
+                $2,
+                $3
+            )"
+        ).bind(self.pk)
+            .bind(scope)
+            .bind(Utc::now())
+            .execute(db)
+            .await
+            .quick_match()?;
+        Ok(())
+    }
+    /// The scopes the client may request for itself
+    pub async fn lookup_scopes(
+        &self, db: &SqlitePool
+    ) -> Result<Vec<String>, RustersError> {
+        let scopes = query_as::<_, (String,)>("
+            select scope
+            from ConsumerScopes
+            where consumer_pk = $1
+            order by scope"
+        ).bind(self.pk)
+            .fetch_all(db)
+            .await
+            .quick_match()?;
+        Ok(scopes.into_iter().map(|s| s.0).collect())
+    }
+    /// Checks a presented client secret against the current secret and, within
+    /// the overlap window, the previous one. Public clients must present none
+    fn check_secret<'a>(&self, secret: Option<&'a str>) -> Result<(), RustersError> {
+        let valid = match (&self.secret_hash, secret) {
+            (Some(hash), Some(secret)) => {
+                let previous = match (
+                    &self.previous_secret_hash, self.previous_secret_expired_dt
+                ) {
+                    (Some(previous), Some(until)) if until > Utc::now() => Some(previous),
+                    _ => None,
+                };
+                Secure::validate(secret, hash)?
+                    || previous.map_or(Ok(false), |p| Secure::validate(secret, p))?
+            },
+            (None, None) => true,
+            _ => false,
+        };
+        if valid && self.is_active {
+            Ok(())
+        } else {
+            Err(RustersError::InvalidCredentialsError)
         }
     }
+    /// The active consumer `name` if `secret` is one of its secrets
+    pub async fn authenticate<'a>(
+        db: &SqlitePool, name: &'a str, secret: &'a str
+    ) -> Result<Self, RustersError> {
+        Self::authenticate_client(db, name, Some(secret)).await
+    }
+    /// `authenticate` which also lets public clients through on their name
+    /// alone when they present no secret
+    pub(crate) async fn authenticate_client<'a>(
+        db: &SqlitePool, name: &'a str, secret: Option<&'a str>
+    ) -> Result<Self, RustersError> {
+        let consumer = Self::lookup(db, name)
+            .await
+            .map_err(|_| RustersError::InvalidCredentialsError)?;
+        consumer.check_secret(secret)?;
+        Ok(consumer)
+    }
 }
@@ -0,0 +1,133 @@
+use {
+    crate::{
+        error::{
+            MatchRustersError,
+            RustersError,
+        },
+        token::{
+            consumable_token::consumer::Consumer,
+            oauth::OAuthErrorCode,
+            refresh_token::RefreshToken,
+            Token,
+        },
+    },
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+    sqlx::{ FromRow, SqlitePool, query_as, },
+};
+/// What a protected resource is told about a presented token, shaped as the
+/// RFC 7662 introspection response. Inactive tokens carry nothing else
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Introspection {
+    pub active: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    /// Always `Bearer` for an active token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    /// `access_token` or `refresh_token` for tokens issued as either, in the
+    /// vocabulary of the `token_type_hint` request parameter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_use: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exp: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iat: Option<i64>,
+}
+/// Who a token was issued to, from whichever of `OAuthTokens`,
+/// `ConsumableTokens` and `RefreshTokens` it appears in
+#[derive(FromRow)]
+struct Issuance {
+    consumer_pk: Option<i64>,
+    consumer_name: Option<String>,
+    user_pk: Option<i64>,
+    is_refresh: Option<bool>,
+    is_oauth: bool,
+}
+impl Issuance {
+    async fn lookup(db: &SqlitePool, token: &Token) -> Result<Self, RustersError> {
+        query_as::<_, Self>("
+            select
+                c.pk as consumer_pk,
+                c.name as consumer_name,
+                coalesce(o.user_pk, f.user_pk) as user_pk,
+                coalesce(o.is_refresh, r.token_pk = t.pk) as is_refresh,
+                o.token_pk is not null as is_oauth
+            from Tokens as t
+            left join OAuthTokens as o
+            on o.token_pk = t.pk
+            left join ConsumableTokens as ct
+            on ct.token_pk = t.pk
+            left join Consumers as c
+            on c.pk = coalesce(o.consumer_pk, ct.consumer_pk)
+            left join RefreshTokens as r
+            on r.token_pk = t.pk
+            or r.access_token_pk = t.pk
+            left join RefreshTokenFamilies as f
+            on f.pk = r.family_pk
+            where t.pk = $1"
+        ).bind(token.get_pk())
+            .fetch_one(db)
+            .await
+            .quick_match()
+    }
+}
+impl Token {
+    /// Introspects the token presented as `hash`. Unknown and expired tokens,
+    /// and tokens not issued through OAuth such as session or reset tokens,
+    /// are reported inactive rather than failing
+    pub async fn introspect<'a>(
+        db: &SqlitePool, hash: &'a str
+    ) -> Result<Introspection, RustersError> {
+        let token = match Self::possible(db, hash).await? {
+            Some(token) => token,
+            None => return Ok(Introspection::default()),
+        };
+        let issuance = Issuance::lookup(db, &token).await?;
+        if !issuance.is_oauth {
+            return Ok(Introspection::default());
+        }
+        let scopes = token.lookup_scopes(db).await?;
+        Ok(Introspection {
+            active: true,
+            scope: Some(scopes.join(" ")).filter(|s| !s.is_empty()),
+            client_id: issuance.consumer_name,
+            sub: issuance.user_pk.map(|pk| pk.to_string()),
+            token_type: Some("Bearer".to_string()),
+            token_use: issuance.is_refresh.map(|is_refresh| match is_refresh {
+                true => "refresh_token".to_string(),
+                false => "access_token".to_string(),
+            }),
+            exp: Some(token.get_expired_dt().timestamp()),
+            iat: Some(token.get_created_dt().timestamp()),
+        })
+    }
+    /// Revokes the token presented as `hash` on behalf of `consumer`, which
+    /// must be the consumer it was issued to. Revoking a refresh token revokes
+    /// its whole family. Unknown and expired tokens are ignored, as RFC 7009
+    /// asks
+    pub async fn revoke<'a>(
+        db: &SqlitePool, consumer: &Consumer, hash: &'a str
+    ) -> Result<(), RustersError> {
+        let token = match Self::possible(db, hash).await? {
+            Some(token) => token,
+            None => return Ok(()),
+        };
+        let issuance = Issuance::lookup(db, &token).await?;
+        if issuance.consumer_pk != Some(consumer.get_pk()) {
+            return Err(RustersError::OAuthError(OAuthErrorCode::UnauthorizedClient));
+        }
+        if issuance.is_refresh == Some(true) {
+            if let Ok(refresh) = RefreshToken::lookup(db, hash).await {
+                RefreshToken::revoke_family(db, refresh.get_family_pk()).await?;
+            }
+        }
+        token.expire(db).await
+    }
+}
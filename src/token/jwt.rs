@@ -0,0 +1,327 @@
+use {
+    base64::URL_SAFE_NO_PAD,
+    chrono::{
+        DateTime,
+        Duration,
+        TimeZone,
+        Utc,
+    },
+    crate::{
+        error::RustersError,
+        session::Session,
+        user::User,
+    },
+    ed25519_dalek::{
+        Signature,
+        Signer,
+        SigningKey,
+        Verifier,
+    },
+    hmac::{
+        Hmac,
+        Mac,
+    },
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+    serde_json::{
+        json,
+        Value,
+    },
+    sha2::Sha256,
+};
+/// A key JWTs are signed and validated with, named by its key id
+#[derive(Clone)]
+pub enum JwtKey {
+    Hs256 { kid: String, secret: Vec<u8> },
+    EdDsa { kid: String, key: SigningKey },
+}
+impl std::fmt::Debug for JwtKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("JwtKey")
+            .field("kid", &self.get_kid())
+            .field("alg", &self.get_alg())
+            .finish()
+    }
+}
+impl JwtKey {
+    pub fn hs256(kid: impl Into<String>, secret: impl Into<Vec<u8>>) -> Self {
+        JwtKey::Hs256 { kid: kid.into(), secret: secret.into() }
+    }
+    pub fn ed25519(kid: impl Into<String>, private: &[u8; 32]) -> Self {
+        JwtKey::EdDsa { kid: kid.into(), key: SigningKey::from_bytes(private) }
+    }
+    /// A fresh Ed25519 key pair
+    pub fn generate_ed25519(kid: impl Into<String>) -> Self {
+        let key = SigningKey::generate(&mut rand::rngs::OsRng);
+        JwtKey::EdDsa { kid: kid.into(), key }
+    }
+    pub fn get_kid(&self) -> &str {
+        match self {
+            JwtKey::Hs256 { kid, .. } => kid,
+            JwtKey::EdDsa { kid, .. } => kid,
+        }
+    }
+    /// The JOSE `alg` of the key
+    pub fn get_alg(&self) -> &'static str {
+        match self {
+            JwtKey::Hs256 { .. } => "HS256",
+            JwtKey::EdDsa { .. } => "EdDSA",
+        }
+    }
+    fn hmac(secret: &[u8], input: &[u8]) -> Result<Hmac<Sha256>, RustersError> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret)
+            .map_err(|_| RustersError::NoSigningKeyError)?;
+        mac.update(input);
+        Ok(mac)
+    }
+    /// Signs the JWS signing input, the encoded header and payload joined by a
+    /// `.`
+    pub fn sign(&self, input: &[u8]) -> Result<Vec<u8>, RustersError> {
+        match self {
+            JwtKey::Hs256 { secret, .. } => {
+                Ok(Self::hmac(secret, input)?.finalize().into_bytes().to_vec())
+            },
+            JwtKey::EdDsa { key, .. } => {
+                Ok(key.sign(input).to_bytes().to_vec())
+            },
+        }
+    }
+    pub fn verify(&self, input: &[u8], signature: &[u8]) -> bool {
+        match self {
+            JwtKey::Hs256 { secret, .. } => {
+                match Self::hmac(secret, input) {
+                    Ok(mac) => mac.verify_slice(signature).is_ok(),
+                    Err(_) => false,
+                }
+            },
+            JwtKey::EdDsa { key, .. } => {
+                match Signature::from_slice(signature) {
+                    Ok(sig) => key.verifying_key().verify(input, &sig).is_ok(),
+                    Err(_) => false,
+                }
+            },
+        }
+    }
+    /// The public half of the key as a JWK, or `None` for symmetric keys
+    pub fn public_jwk(&self) -> Option<Value> {
+        match self {
+            JwtKey::Hs256 { .. } => None,
+            JwtKey::EdDsa { kid, key } => Some(json!({
+                "kty": "OKP",
+                "crv": "Ed25519",
+                "alg": "EdDSA",
+                "use": "sig",
+                "kid": kid,
+                "x": base64::encode_config(
+                    key.verifying_key().to_bytes(),
+                    URL_SAFE_NO_PAD
+                ),
+            })),
+        }
+    }
+}
+/// Who issues JWTs and who they are meant for, checked on validation
+#[derive(Clone, Debug)]
+pub struct JwtConfig {
+    pub issuer: String,
+    pub audience: String,
+    /// Clock skew allowed when checking `exp` and `nbf`
+    pub leeway: Duration,
+}
+impl JwtConfig {
+    pub fn new(issuer: impl Into<String>, audience: impl Into<String>) -> Self {
+        JwtConfig {
+            issuer: issuer.into(),
+            audience: audience.into(),
+            leeway: Duration::seconds(30),
+        }
+    }
+}
+#[derive(Deserialize, Serialize)]
+struct Header {
+    alg: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    typ: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kid: Option<String>,
+}
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+impl Audience {
+    fn contains(&self, aud: &str) -> bool {
+        match self {
+            Audience::One(one) => one == aud,
+            Audience::Many(many) => many.iter().any(|a| a == aud),
+        }
+    }
+}
+#[derive(Deserialize, Serialize)]
+struct Claims {
+    iss: String,
+    sub: String,
+    aud: Audience,
+    exp: i64,
+    nbf: i64,
+    iat: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sid: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    preferred_username: Option<String>,
+}
+/// The validated claims of a JWT issued for a `User`
+pub struct Jwt {
+    issuer: String,
+    subject: String,
+    session_pk: Option<i64>,
+    username: Option<String>,
+    issued_dt: DateTime<Utc>,
+    not_before_dt: DateTime<Utc>,
+    expired_dt: DateTime<Utc>,
+    key_id: String,
+}
+impl Jwt {
+    pub fn get_issuer(&self) -> String {
+        self.issuer.clone()
+    }
+    /// The primary key of the `User` the token was issued to, as a string
+    pub fn get_subject(&self) -> String {
+        self.subject.clone()
+    }
+    pub fn get_session_pk(&self) -> Option<i64> {
+        self.session_pk
+    }
+    pub fn get_username(&self) -> Option<String> {
+        self.username.clone()
+    }
+    pub fn get_issued_dt(&self) -> DateTime<Utc> {
+        self.issued_dt
+    }
+    pub fn get_not_before_dt(&self) -> DateTime<Utc> {
+        self.not_before_dt
+    }
+    pub fn get_expired_dt(&self) -> DateTime<Utc> {
+        self.expired_dt
+    }
+    pub fn get_key_id(&self) -> String {
+        self.key_id.clone()
+    }
+    pub fn default_expires() -> Duration {
+        Duration::minutes(15)
+    }
+    fn encode(value: &impl Serialize) -> Result<String, RustersError> {
+        let json = serde_json::to_vec(value)
+            .map_err(|_| RustersError::InvalidTokenError)?;
+        Ok(base64::encode_config(json, URL_SAFE_NO_PAD))
+    }
+    fn decode<T: for<'de> Deserialize<'de>>(part: &str) -> Result<T, RustersError> {
+        let json = base64::decode_config(part, URL_SAFE_NO_PAD)
+            .map_err(|_| RustersError::InvalidTokenError)?;
+        serde_json::from_slice(&json).map_err(|_| RustersError::InvalidTokenError)
+    }
+    /// Issues a JWT for `user`, tied to `session` when one is given
+    pub fn issue(
+        config: &JwtConfig,
+        key: &JwtKey,
+        user: &User,
+        session: Option<&Session>,
+        expires: Option<Duration>,
+    ) -> Result<String, RustersError> {
+        let now = Utc::now();
+        let claims = Claims {
+            iss: config.issuer.clone(),
+            sub: user.get_pk().to_string(),
+            aud: Audience::One(config.audience.clone()),
+            exp: (now + expires.unwrap_or_else(Self::default_expires)).timestamp(),
+            nbf: now.timestamp(),
+            iat: now.timestamp(),
+            sid: session.map(|s| s.get_pk()),
+            preferred_username: Some(user.get_username()),
+        };
+        Self::sign(key, &claims)
+    }
+    /// Signs any set of `claims` with `key`
+    pub(crate) fn sign(
+        key: &JwtKey, claims: &impl Serialize
+    ) -> Result<String, RustersError> {
+        let header = Header {
+            alg: key.get_alg().to_string(),
+            typ: Some("JWT".to_string()),
+            kid: Some(key.get_kid().to_string()),
+        };
+        let input = format!("{}.{}", Self::encode(&header)?, Self::encode(claims)?);
+        let signature = key.sign(input.as_bytes())?;
+        Ok(format!(
+            "{}.{}",
+            input,
+            base64::encode_config(signature, URL_SAFE_NO_PAD)
+        ))
+    }
+    fn timestamp(secs: i64) -> Result<DateTime<Utc>, RustersError> {
+        Utc.timestamp_opt(secs, 0)
+            .single()
+            .ok_or(RustersError::InvalidTokenError)
+    }
+    /// Validates `presented` against the key named by its `kid`, refusing any
+    /// `alg` other than that of the key, then checks `iss`, `aud`, `exp` and
+    /// `nbf`
+    pub fn validate<'a>(
+        config: &JwtConfig, keys: &[JwtKey], presented: &'a str
+    ) -> Result<Self, RustersError> {
+        let mut parts = presented.trim().split('.');
+        let (header, payload, signature) = match (
+            parts.next(), parts.next(), parts.next(), parts.next()
+        ) {
+            (Some(h), Some(p), Some(s), None) => (h, p, s),
+            _ => return Err(RustersError::InvalidTokenError),
+        };
+        let parsed = Self::decode::<Header>(header)?;
+        let kid = parsed.kid.ok_or(RustersError::InvalidTokenError)?;
+        let key = keys.iter()
+            .find(|k| k.get_kid() == kid)
+            .ok_or(RustersError::InvalidTokenError)?;
+        if parsed.alg != key.get_alg() {
+            return Err(RustersError::InvalidTokenError);
+        }
+        let signature = base64::decode_config(signature, URL_SAFE_NO_PAD)
+            .map_err(|_| RustersError::InvalidTokenError)?;
+        let input = format!("{}.{}", header, payload);
+        if !key.verify(input.as_bytes(), &signature) {
+            return Err(RustersError::InvalidTokenError);
+        }
+        let claims = Self::decode::<Claims>(payload)?;
+        let now = Utc::now();
+        let expired_dt = Self::timestamp(claims.exp)?;
+        let not_before_dt = Self::timestamp(claims.nbf)?;
+        if claims.iss != config.issuer
+            || !claims.aud.contains(&config.audience)
+            || expired_dt + config.leeway <= now
+            || not_before_dt - config.leeway > now
+        {
+            return Err(RustersError::InvalidTokenError);
+        }
+        Ok(Jwt {
+            issuer: claims.iss,
+            subject: claims.sub,
+            session_pk: claims.sid,
+            username: claims.preferred_username,
+            issued_dt: Self::timestamp(claims.iat)?,
+            not_before_dt,
+            expired_dt,
+            key_id: kid,
+        })
+    }
+    /// The JWKS document publishing the public keys among `keys`
+    pub fn jwks(keys: &[JwtKey]) -> Value {
+        json!({
+            "keys": keys.iter()
+                .filter_map(|k| k.public_jwk())
+                .collect::<Vec<Value>>(),
+        })
+    }
+}
@@ -0,0 +1,589 @@
+#[cfg(feature = "jwt")]
+pub mod oidc;
+use {
+    base64::URL_SAFE_NO_PAD,
+    chrono::{
+        DateTime,
+        Duration,
+        Utc,
+    },
+    crate::{
+        error::{
+            MatchRustersError,
+            RustersError,
+        },
+        hash::{
+            Basic,
+            Hash,
+        },
+        token::{
+            consumable_token::{
+                ConsumableToken,
+                consumer::Consumer,
+            },
+            refresh_token::RefreshToken,
+            Token,
+        },
+        user::User,
+    },
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+    sha2::{
+        Digest,
+        Sha256,
+    },
+    sqlx::{ FromRow, SqlitePool, query, query_as, },
+};
+/// Error codes from RFC 6749 sections 4.1.2.1 and 5.2
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OAuthErrorCode {
+    InvalidRequest,
+    InvalidClient,
+    InvalidGrant,
+    UnauthorizedClient,
+    UnsupportedGrantType,
+    UnsupportedResponseType,
+    InvalidScope,
+    ServerError,
+}
+impl OAuthErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OAuthErrorCode::InvalidRequest => "invalid_request",
+            OAuthErrorCode::InvalidClient => "invalid_client",
+            OAuthErrorCode::InvalidGrant => "invalid_grant",
+            OAuthErrorCode::UnauthorizedClient => "unauthorized_client",
+            OAuthErrorCode::UnsupportedGrantType => "unsupported_grant_type",
+            OAuthErrorCode::UnsupportedResponseType => "unsupported_response_type",
+            OAuthErrorCode::InvalidScope => "invalid_scope",
+            OAuthErrorCode::ServerError => "server_error",
+        }
+    }
+    /// The code reported to the client for `error`
+    pub fn from_error(error: &RustersError) -> Self {
+        match error {
+            RustersError::OAuthError(code) => *code,
+            RustersError::InvalidCredentialsError => OAuthErrorCode::InvalidClient,
+            RustersError::InvalidTokenError => OAuthErrorCode::InvalidGrant,
+            RustersError::TokenReuseError => OAuthErrorCode::InvalidGrant,
+            RustersError::InsufficientScope(_) => OAuthErrorCode::InvalidScope,
+            _ => OAuthErrorCode::ServerError,
+        }
+    }
+}
+/// The JSON body of a failed token request
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: &'static str,
+    pub error_description: String,
+}
+impl From<&RustersError> for ErrorResponse {
+    fn from(error: &RustersError) -> Self {
+        let code = OAuthErrorCode::from_error(error);
+        let error_description = match code {
+            OAuthErrorCode::ServerError => "The server could not handle the request".to_string(),
+            _ => error.to_string(),
+        };
+        ErrorResponse { error: code.as_str(), error_description }
+    }
+}
+/// The parameters of a request to the authorization endpoint
+#[derive(Debug, Default, Deserialize)]
+pub struct AuthorizationRequest {
+    pub response_type: String,
+    pub client_id: String,
+    pub redirect_uri: Option<String>,
+    pub scope: Option<String>,
+    pub state: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+    /// Echoed in the ID token to tie it to the client's session
+    pub nonce: Option<String>,
+}
+/// A granted authorization, to be sent back to the client by redirecting the
+/// user agent to `get_location`
+#[derive(Debug)]
+pub struct AuthorizationResponse {
+    redirect_uri: String,
+    code: String,
+    state: Option<String>,
+}
+impl AuthorizationResponse {
+    pub fn get_redirect_uri(&self) -> String {
+        self.redirect_uri.clone()
+    }
+    pub fn get_code(&self) -> String {
+        self.code.clone()
+    }
+    pub fn get_state(&self) -> Option<String> {
+        self.state.clone()
+    }
+    pub fn get_location(&self) -> String {
+        let separator = if self.redirect_uri.contains('?') { '&' } else { '?' };
+        let mut location = format!(
+            "{}{}code={}",
+            self.redirect_uri, separator, urlencoding::encode(&self.code),
+        );
+        if let Some(state) = &self.state {
+            location.push_str("&state=");
+            location.push_str(&urlencoding::encode(state));
+        }
+        location
+    }
+}
+/// The parameters of a request to the token endpoint. Clients authenticate
+/// with `client_id` and, when confidential, `client_secret`
+#[derive(Debug, Default, Deserialize)]
+pub struct TokenRequest {
+    pub grant_type: String,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub code_verifier: Option<String>,
+    pub refresh_token: Option<String>,
+    pub scope: Option<String>,
+}
+/// The JSON body of a successful token request
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    pub scope: String,
+    /// Issued by the code exchange when `openid` was granted and the server
+    /// has an `oidc` provider
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+/// A one-time code issued by the authorization endpoint. The code itself is a
+/// `ConsumableToken` of the client it was issued to, carrying the granted
+/// scopes
+#[derive(FromRow)]
+pub struct AuthorizationCode {
+    pk: i64,
+    consumable_token_pk: i64,
+    user_pk: i64,
+    redirect_uri: String,
+    /// Whether the authorization request named `redirect_uri`, which the
+    /// token request must then repeat
+    is_redirect_uri_explicit: bool,
+    code_challenge: String,
+    nonce: Option<String>,
+    created_dt: DateTime<Utc>,
+}
+impl AuthorizationCode {
+    pub fn get_pk(&self) -> i64 {
+        self.pk
+    }
+    pub fn get_consumable_token_pk(&self) -> i64 {
+        self.consumable_token_pk
+    }
+    pub fn get_user_pk(&self) -> i64 {
+        self.user_pk
+    }
+    pub fn get_redirect_uri(&self) -> String {
+        self.redirect_uri.clone()
+    }
+    pub fn get_is_redirect_uri_explicit(&self) -> bool {
+        self.is_redirect_uri_explicit
+    }
+    pub fn get_code_challenge(&self) -> String {
+        self.code_challenge.clone()
+    }
+    pub fn get_nonce(&self) -> Option<String> {
+        self.nonce.clone()
+    }
+    pub fn get_created_dt(&self) -> DateTime<Utc> {
+        self.created_dt
+    }
+    pub async fn lookup(
+        db: &SqlitePool, consumable: &ConsumableToken
+    ) -> Result<Self, RustersError> {
+        query_as::<_, Self>("
+            select
+                pk,
+                consumable_token_pk,
+                user_pk,
+                redirect_uri,
+                is_redirect_uri_explicit,
+                code_challenge,
+                nonce,
+                created_dt
+            from AuthorizationCodes
+            where consumable_token_pk = $1"
+        ).bind(consumable.get_pk())
+            .fetch_one(db)
+            .await
+            .quick_match()
+    }
+}
+/// An access or refresh `Token` issued by the token endpoint, tying it to the
+/// client it was issued to and, outside the client-credentials grant, the user
+/// who authorized it
+#[derive(FromRow)]
+pub struct OAuthToken {
+    pk: i64,
+    token_pk: i64,
+    consumer_pk: i64,
+    user_pk: Option<i64>,
+    is_refresh: bool,
+    created_dt: DateTime<Utc>,
+}
+impl OAuthToken {
+    pub fn get_pk(&self) -> i64 {
+        self.pk
+    }
+    pub fn get_token_pk(&self) -> i64 {
+        self.token_pk
+    }
+    pub fn get_consumer_pk(&self) -> i64 {
+        self.consumer_pk
+    }
+    pub fn get_user_pk(&self) -> Option<i64> {
+        self.user_pk
+    }
+    pub fn get_is_refresh(&self) -> bool {
+        self.is_refresh
+    }
+    pub fn get_created_dt(&self) -> DateTime<Utc> {
+        self.created_dt
+    }
+    /// The token issued as `hash`, whether or not it has expired
+    pub async fn lookup<'a>(
+        db: &SqlitePool, hash: &'a str
+    ) -> Result<Self, RustersError> {
+        query_as::<_, Self>("
+            select
+                o.pk,
+                o.token_pk,
+                o.consumer_pk,
+                o.user_pk,
+                o.is_refresh,
+                o.created_dt
+            from OAuthTokens as o
+            join Tokens as t
+            on t.pk = o.token_pk
+            where t.hash = $1"
+        ).bind(hash)
+            .fetch_one(db)
+            .await
+            .quick_match()
+    }
+    pub async fn lookup_scopes(
+        &self, db: &SqlitePool
+    ) -> Result<Vec<String>, RustersError> {
+        let scopes = query_as::<_, (String,)>("
+            select scope
+            from TokenScopes
+            where token_pk = $1
+            order by scope"
+        ).bind(self.token_pk)
+            .fetch_all(db)
+            .await
+            .quick_match()?;
+        Ok(scopes.into_iter().map(|s| s.0).collect())
+    }
+    async fn insert(
+        db: &SqlitePool,
+        token: &Token,
+        consumer: &Consumer,
+        user_pk: Option<i64>,
+        is_refresh: bool,
+        scopes: &[String],
+    ) -> Result<(), RustersError> {
+        for scope in scopes {
+            token.add_scope(db, scope).await?;
+        }
+        query("
+            insert into OAuthTokens (
+                token_pk,
+                consumer_pk,
+                user_pk,
+                is_refresh,
+                created_dt
+            ) values (
+                $1,
+                $2,
+                $3,
+                $4,
+                $5
+            )"
+        ).bind(token.get_pk())
+            .bind(consumer.get_pk())
+            .bind(user_pk)
+            .bind(is_refresh)
+            .bind(Utc::now())
+            .execute(db)
+            .await
+            .quick_match()?;
+        Ok(())
+    }
+}
+/// An OAuth 2.0 authorization server over the registered `Consumer`s. Only the
+/// authorization-code grant with `S256` PKCE, the client-credentials grant and
+/// refresh are supported; refresh tokens rotate as a `RefreshToken` family.
+/// Client credentials are only granted scopes registered for the consumer
+pub struct OAuthServer {
+    /// How long an authorization code can be exchanged
+    pub code_expires: Duration,
+    pub access_expires: Duration,
+    pub refresh_expires: Duration,
+    /// Issues ID tokens for codes granted the `openid` scope
+    #[cfg(feature = "jwt")]
+    pub oidc: Option<oidc::OidcProvider>,
+}
+impl Default for OAuthServer {
+    fn default() -> Self {
+        OAuthServer {
+            code_expires: Duration::minutes(10),
+            access_expires: RefreshToken::default_access_expires(),
+            refresh_expires: RefreshToken::default_refresh_expires(),
+            #[cfg(feature = "jwt")]
+            oidc: None,
+        }
+    }
+}
+fn split_scope(scope: Option<&str>) -> Vec<String> {
+    let mut scopes = scope.unwrap_or_default()
+        .split_whitespace()
+        .map(|s| s.to_string())
+        .collect::<Vec<String>>();
+    scopes.sort();
+    scopes.dedup();
+    scopes
+}
+/// The `S256` code challenge of `verifier`
+pub fn pkce_challenge<'a>(verifier: &'a str) -> String {
+    base64::encode_config(Sha256::digest(verifier.as_bytes()), URL_SAFE_NO_PAD)
+}
+fn invalid_request() -> RustersError {
+    RustersError::OAuthError(OAuthErrorCode::InvalidRequest)
+}
+impl OAuthServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    async fn authenticate_client(
+        db: &SqlitePool, request: &TokenRequest
+    ) -> Result<Consumer, RustersError> {
+        let client_id = request.client_id.as_deref().ok_or_else(invalid_request)?;
+        Consumer::authenticate_client(
+            db, client_id, request.client_secret.as_deref()
+        ).await
+    }
+    /// Handles an authorization request `user` has approved, issuing a code
+    /// bound to the client, redirect URI and PKCE challenge. Errors are
+    /// returned rather than redirected
+    pub async fn authorize(
+        &self, db: &SqlitePool, user: &User, request: &AuthorizationRequest
+    ) -> Result<AuthorizationResponse, RustersError> {
+        let consumer = Consumer::lookup(db, &request.client_id)
+            .await
+            .map_err(|_| RustersError::InvalidCredentialsError)?;
+        let registered = consumer.lookup_redirect_uris(db).await?;
+        if registered.is_empty() {
+            return Err(RustersError::OAuthError(OAuthErrorCode::UnauthorizedClient));
+        }
+        let redirect_uri = match &request.redirect_uri {
+            Some(uri) if registered.contains(uri) => uri.clone(),
+            None if registered.len() == 1 => registered[0].clone(),
+            _ => return Err(invalid_request()),
+        };
+        if request.response_type != "code" {
+            return Err(RustersError::OAuthError(OAuthErrorCode::UnsupportedResponseType));
+        }
+        let code_challenge = match (
+            &request.code_challenge, request.code_challenge_method.as_deref()
+        ) {
+            (Some(challenge), Some("S256")) if challenge.len() == 43 => challenge,
+            _ => return Err(invalid_request()),
+        };
+        let scopes = split_scope(request.scope.as_deref());
+        let token = Token::insert_with_scopes(
+            db,
+            Basic::rand()?,
+            Some(self.code_expires),
+            &scopes.iter().map(|s| s.as_str()).collect::<Vec<&str>>(),
+        ).await?;
+        let consumable = ConsumableToken::insert(db, &token, &consumer).await?;
+        query("
+            insert into AuthorizationCodes (
+                consumable_token_pk,
+                user_pk,
+                redirect_uri,
+                is_redirect_uri_explicit,
+                code_challenge,
+                nonce,
+                created_dt
+            ) values (
+                $1,
+                $2,
+                $3,
+                $4,
+                $5,
+                $6,
+                $7
+            )"
+        ).bind(consumable.get_pk())
+            .bind(user.get_pk())
+            .bind(&redirect_uri)
+            .bind(request.redirect_uri.is_some())
+            .bind(code_challenge)
+            .bind(&request.nonce)
+            .bind(Utc::now())
+            .execute(db)
+            .await
+            .quick_match()?;
+        Ok(AuthorizationResponse {
+            redirect_uri,
+            code: token.get_hash(),
+            state: request.state.clone(),
+        })
+    }
+    /// Handles a token request for any supported `grant_type`
+    pub async fn token(
+        &self, db: &SqlitePool, request: &TokenRequest
+    ) -> Result<TokenResponse, RustersError> {
+        match request.grant_type.as_str() {
+            "authorization_code" => self.exchange_code(db, request).await,
+            "client_credentials" => self.client_credentials(db, request).await,
+            "refresh_token" => self.refresh(db, request).await,
+            _ => Err(RustersError::OAuthError(OAuthErrorCode::UnsupportedGrantType)),
+        }
+    }
+    async fn exchange_code(
+        &self, db: &SqlitePool, request: &TokenRequest
+    ) -> Result<TokenResponse, RustersError> {
+        let consumer = Self::authenticate_client(db, request).await?;
+        let code = request.code.as_deref().ok_or_else(invalid_request)?;
+        let verifier = request.code_verifier.as_deref().ok_or_else(invalid_request)?;
+        let token = match Token::possible(db, code).await? {
+            Some(token) => token,
+            None => return Err(RustersError::InvalidTokenError),
+        };
+        let consumable = ConsumableToken::lookup(db, &token, &consumer)
+            .await
+            .map_err(|_| RustersError::InvalidTokenError)?;
+        let grant = AuthorizationCode::lookup(db, &consumable).await?;
+        // RFC 6749 4.1.3: a redirect URI named in the authorization request
+        // must be repeated exactly
+        let redirect_matches = match &request.redirect_uri {
+            Some(uri) => *uri == grant.redirect_uri,
+            None => !grant.is_redirect_uri_explicit,
+        };
+        if !redirect_matches || pkce_challenge(verifier) != grant.code_challenge {
+            return Err(RustersError::InvalidTokenError);
+        }
+        let scopes = token.lookup_scopes(db).await?;
+        let consumed = query("
+            update Tokens
+            set expired_dt = $1
+            where pk = $2
+            and expired_dt > $1"
+        ).bind(Utc::now())
+            .bind(token.get_pk())
+            .execute(db)
+            .await
+            .quick_match()?
+            .rows_affected() > 0;
+        if !consumed {
+            return Err(RustersError::InvalidTokenError);
+        }
+        let user = User::lookup_by_pk(db, grant.user_pk).await?;
+        let (access, refresh) = RefreshToken::issue(
+            db, &user, Some(self.access_expires), Some(self.refresh_expires)
+        ).await?;
+        #[cfg(feature = "jwt")]
+        let id_token = match &self.oidc {
+            Some(oidc) if scopes.iter().any(|s| s == oidc::OidcProvider::SCOPE) => {
+                Some(oidc.id_token(&user, &consumer, grant.nonce.as_deref(), &scopes)?)
+            },
+            _ => None,
+        };
+        let response = Self::respond(
+            db, &consumer, Some(user.get_pk()), access, Some(refresh), scopes
+        ).await?;
+        #[cfg(feature = "jwt")]
+        let response = TokenResponse { id_token, ..response };
+        Ok(response)
+    }
+    async fn client_credentials(
+        &self, db: &SqlitePool, request: &TokenRequest
+    ) -> Result<TokenResponse, RustersError> {
+        let consumer = Self::authenticate_client(db, request).await?;
+        if !consumer.get_is_confidential() {
+            return Err(RustersError::OAuthError(OAuthErrorCode::UnauthorizedClient));
+        }
+        let scopes = split_scope(request.scope.as_deref());
+        let allowed = consumer.lookup_scopes(db).await?;
+        if scopes.iter().any(|s| !allowed.contains(s)) {
+            return Err(RustersError::OAuthError(OAuthErrorCode::InvalidScope));
+        }
+        let access = Token::basic(db, Some(self.access_expires)).await?;
+        Self::respond(db, &consumer, None, access, None, scopes).await
+    }
+    async fn refresh(
+        &self, db: &SqlitePool, request: &TokenRequest
+    ) -> Result<TokenResponse, RustersError> {
+        let consumer = Self::authenticate_client(db, request).await?;
+        let presented = request.refresh_token.as_deref().ok_or_else(invalid_request)?;
+        let current = OAuthToken::lookup(db, presented)
+            .await
+            .map_err(|_| RustersError::InvalidTokenError)?;
+        if !current.is_refresh || current.consumer_pk != consumer.get_pk() {
+            return Err(RustersError::InvalidTokenError);
+        }
+        let granted = current.lookup_scopes(db).await?;
+        let scopes = match &request.scope {
+            Some(scope) => {
+                let requested = split_scope(Some(scope));
+                let missing = requested.iter()
+                    .filter(|s| !granted.contains(s))
+                    .cloned()
+                    .collect::<Vec<String>>();
+                if !missing.is_empty() {
+                    return Err(RustersError::InsufficientScope(missing));
+                }
+                requested
+            },
+            None => granted.clone(),
+        };
+        let (access, refresh) = RefreshToken::refresh(
+            db, presented, Some(self.access_expires), Some(self.refresh_expires)
+        ).await?;
+        // the refresh token keeps every granted scope so a narrowed request
+        // does not shrink later refreshes
+        OAuthToken::insert(db, &refresh, &consumer, current.user_pk, true, &granted).await?;
+        let mut response = Self::respond(
+            db, &consumer, current.user_pk, access, None, scopes
+        ).await?;
+        response.refresh_token = Some(refresh.get_hash());
+        Ok(response)
+    }
+    async fn respond(
+        db: &SqlitePool,
+        consumer: &Consumer,
+        user_pk: Option<i64>,
+        access: Token,
+        refresh: Option<Token>,
+        scopes: Vec<String>,
+    ) -> Result<TokenResponse, RustersError> {
+        OAuthToken::insert(db, &access, consumer, user_pk, false, &scopes).await?;
+        if let Some(refresh) = &refresh {
+            OAuthToken::insert(db, refresh, consumer, user_pk, true, &scopes).await?;
+        }
+        Ok(TokenResponse {
+            access_token: access.get_hash(),
+            token_type: "Bearer".to_string(),
+            expires_in: (access.get_expired_dt() - access.get_created_dt()).num_seconds(),
+            refresh_token: refresh.map(|r| r.get_hash()),
+            scope: scopes.join(" "),
+            id_token: None,
+        })
+    }
+}
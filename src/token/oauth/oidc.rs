@@ -0,0 +1,170 @@
+use {
+    chrono::{
+        Duration,
+        Utc,
+    },
+    crate::{
+        error::RustersError,
+        token::{
+            consumable_token::consumer::Consumer,
+            jwt::{
+                Jwt,
+                JwtKey,
+            },
+            oauth::OAuthToken,
+            Token,
+        },
+        user::User,
+    },
+    serde::Serialize,
+    serde_json::{
+        json,
+        Value,
+    },
+    sqlx::SqlitePool,
+};
+/// The standard claims about a `User` released for the granted scopes: `sub`
+/// always, `preferred_username` for `profile` and `email` for `email`
+#[derive(Debug, Serialize)]
+pub struct UserInfo {
+    pub sub: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preferred_username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+}
+impl UserInfo {
+    pub fn new(user: &User, scopes: &[String]) -> Self {
+        let granted = |scope: &str| scopes.iter().any(|s| s == scope);
+        let email = user.get_email().filter(|_| granted("email"));
+        UserInfo {
+            sub: user.get_pk().to_string(),
+            preferred_username: Some(user.get_username()).filter(|_| granted("profile")),
+            email_verified: email.as_ref().map(|_| user.get_is_email_verified()),
+            email,
+        }
+    }
+}
+#[derive(Serialize)]
+struct IdTokenClaims<'a> {
+    iss: &'a str,
+    aud: String,
+    exp: i64,
+    nbf: i64,
+    iat: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nonce: Option<&'a str>,
+    #[serde(flatten)]
+    user: UserInfo,
+}
+/// The OpenID Connect layer of an `OAuthServer`: ID tokens signed with `key`,
+/// the discovery document and the userinfo response
+#[derive(Clone, Debug)]
+pub struct OidcProvider {
+    pub issuer: String,
+    pub key: JwtKey,
+    pub id_token_expires: Duration,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub userinfo_endpoint: String,
+    pub jwks_uri: String,
+}
+impl OidcProvider {
+    /// The scope a client requests to be issued an ID token
+    pub const SCOPE: &'static str = "openid";
+    /// A provider with its endpoints under `issuer`
+    pub fn new(issuer: impl Into<String>, key: JwtKey) -> Self {
+        let issuer = issuer.into();
+        let base = issuer.trim_end_matches('/');
+        OidcProvider {
+            authorization_endpoint: format!("{}/authorize", base),
+            token_endpoint: format!("{}/token", base),
+            userinfo_endpoint: format!("{}/userinfo", base),
+            jwks_uri: format!("{}/jwks", base),
+            id_token_expires: Duration::minutes(5),
+            key,
+            issuer,
+        }
+    }
+    /// An ID token for `user` meant for `consumer`, echoing the `nonce` of
+    /// the authorization request
+    pub fn id_token<'a>(
+        &self,
+        user: &User,
+        consumer: &Consumer,
+        nonce: Option<&'a str>,
+        scopes: &[String],
+    ) -> Result<String, RustersError> {
+        let now = Utc::now();
+        let claims = IdTokenClaims {
+            iss: &self.issuer,
+            aud: consumer.get_name(),
+            exp: (now + self.id_token_expires).timestamp(),
+            nbf: now.timestamp(),
+            iat: now.timestamp(),
+            nonce,
+            user: UserInfo::new(user, scopes),
+        };
+        Jwt::sign(&self.key, &claims)
+    }
+    /// The document served at `/.well-known/openid-configuration`
+    pub fn discovery(&self) -> Value {
+        json!({
+            "issuer": self.issuer,
+            "authorization_endpoint": self.authorization_endpoint,
+            "token_endpoint": self.token_endpoint,
+            "userinfo_endpoint": self.userinfo_endpoint,
+            "jwks_uri": self.jwks_uri,
+            "response_types_supported": [ "code" ],
+            "grant_types_supported": [
+                "authorization_code",
+                "client_credentials",
+                "refresh_token",
+            ],
+            "subject_types_supported": [ "public" ],
+            "id_token_signing_alg_values_supported": [ self.key.get_alg() ],
+            "scopes_supported": [ Self::SCOPE, "profile", "email" ],
+            "claims_supported": [
+                "iss",
+                "sub",
+                "aud",
+                "exp",
+                "iat",
+                "nonce",
+                "preferred_username",
+                "email",
+                "email_verified",
+            ],
+            "token_endpoint_auth_methods_supported": [ "client_secret_post", "none" ],
+            "code_challenge_methods_supported": [ "S256" ],
+        })
+    }
+    /// The document served at `jwks_uri`
+    pub fn jwks(&self) -> Value {
+        Jwt::jwks(std::slice::from_ref(&self.key))
+    }
+    /// The claims about the user an unexpired access token granted `openid`
+    /// was issued for
+    pub async fn userinfo<'a>(
+        &self, db: &SqlitePool, access_token: &'a str
+    ) -> Result<UserInfo, RustersError> {
+        let issued = OAuthToken::lookup(db, access_token)
+            .await
+            .map_err(|_| RustersError::InvalidTokenError)?;
+        let user_pk = match issued.get_user_pk() {
+            Some(pk) if !issued.get_is_refresh() => pk,
+            _ => return Err(RustersError::InvalidTokenError),
+        };
+        if Token::possible(db, access_token).await?.is_none() {
+            return Err(RustersError::InvalidTokenError);
+        }
+        let scopes = issued.lookup_scopes(db).await?;
+        if !scopes.iter().any(|s| s == Self::SCOPE) {
+            return Err(RustersError::InsufficientScope(vec![Self::SCOPE.to_string()]));
+        }
+        let user = User::lookup_by_pk(db, user_pk).await?;
+        Ok(UserInfo::new(&user, &scopes))
+    }
+}
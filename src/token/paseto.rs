@@ -0,0 +1,333 @@
+use {
+    base64::URL_SAFE_NO_PAD,
+    blake2::{
+        digest::consts::{
+            U32,
+            U56,
+        },
+        Blake2bMac,
+    },
+    chacha20::{
+        cipher::{
+            KeyIvInit,
+            StreamCipher,
+        },
+        XChaCha20,
+    },
+    chrono::{
+        DateTime,
+        Duration,
+        Utc,
+    },
+    crate::{
+        error::RustersError,
+        session::Session,
+        user::User,
+    },
+    ed25519_dalek::{
+        Signature,
+        Signer,
+        SigningKey,
+        Verifier,
+        VerifyingKey,
+    },
+    hmac::Mac,
+    rand::RngCore,
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+};
+const LOCAL_HEADER: &str = "v4.local.";
+const PUBLIC_HEADER: &str = "v4.public.";
+/// A key PASETO v4 tokens are issued and checked with, named by the key id
+/// carried in the token footer
+#[derive(Clone)]
+pub enum PasetoKey {
+    /// A symmetric key for `v4.local` tokens
+    Local { kid: String, key: [u8; 32] },
+    /// An Ed25519 key pair for `v4.public` tokens
+    Public { kid: String, key: SigningKey },
+    /// The public half of a `Public` key, which can only verify
+    Verifying { kid: String, key: VerifyingKey },
+}
+impl std::fmt::Debug for PasetoKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("PasetoKey")
+            .field("kid", &self.get_kid())
+            .field("header", &self.get_header())
+            .finish()
+    }
+}
+impl PasetoKey {
+    pub fn generate_local(kid: impl Into<String>) -> Self {
+        let mut key = [0_u8; 32];
+        rand::rngs::OsRng.fill_bytes(&mut key);
+        PasetoKey::Local { kid: kid.into(), key }
+    }
+    pub fn generate_public(kid: impl Into<String>) -> Self {
+        let key = SigningKey::generate(&mut rand::rngs::OsRng);
+        PasetoKey::Public { kid: kid.into(), key }
+    }
+    pub fn get_kid(&self) -> &str {
+        match self {
+            PasetoKey::Local { kid, .. } => kid,
+            PasetoKey::Public { kid, .. } => kid,
+            PasetoKey::Verifying { kid, .. } => kid,
+        }
+    }
+    /// The token header the key issues or accepts
+    pub fn get_header(&self) -> &'static str {
+        match self {
+            PasetoKey::Local { .. } => LOCAL_HEADER,
+            _ => PUBLIC_HEADER,
+        }
+    }
+    /// The key to hand to services which only need to verify
+    pub fn verifying(&self) -> Option<Self> {
+        match self {
+            PasetoKey::Local { .. } => None,
+            PasetoKey::Public { kid, key } => Some(PasetoKey::Verifying {
+                kid: kid.clone(),
+                key: key.verifying_key(),
+            }),
+            PasetoKey::Verifying { .. } => Some(self.clone()),
+        }
+    }
+}
+/// Pre-authentication encoding, which binds every piece of a token into what
+/// is signed or authenticated
+fn pae(pieces: &[&[u8]]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&(pieces.len() as u64 & (u64::MAX >> 1)).to_le_bytes());
+    for piece in pieces {
+        out.extend_from_slice(&(piece.len() as u64 & (u64::MAX >> 1)).to_le_bytes());
+        out.extend_from_slice(piece);
+    }
+    out
+}
+fn blake2b_mac<'a, M: Mac + blake2::digest::KeyInit>(
+    key: &[u8], parts: &[&'a [u8]]
+) -> Result<Vec<u8>, RustersError> {
+    let mut mac = <M as blake2::digest::KeyInit>::new_from_slice(key)
+        .map_err(|_| RustersError::NoSigningKeyError)?;
+    for part in parts {
+        mac.update(part);
+    }
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+fn encode(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, URL_SAFE_NO_PAD)
+}
+fn decode(part: &str) -> Result<Vec<u8>, RustersError> {
+    base64::decode_config(part, URL_SAFE_NO_PAD)
+        .map_err(|_| RustersError::InvalidTokenError)
+}
+fn append_footer(mut token: String, footer: &[u8]) -> String {
+    if !footer.is_empty() {
+        token.push('.');
+        token.push_str(&encode(footer));
+    }
+    token
+}
+/// Splits `token` into its body and footer after checking the header
+fn split<'a>(token: &'a str, header: &str) -> Result<(Vec<u8>, Vec<u8>), RustersError> {
+    let rest = token.trim()
+        .strip_prefix(header)
+        .ok_or(RustersError::InvalidTokenError)?;
+    match rest.split_once('.') {
+        Some((body, footer)) => Ok((decode(body)?, decode(footer)?)),
+        None => Ok((decode(rest)?, Vec::new())),
+    }
+}
+/// Encryption key, XChaCha20 nonce and authentication key
+type LocalKeys = (Vec<u8>, Vec<u8>, Vec<u8>);
+fn local_keys(key: &[u8; 32], nonce: &[u8]) -> Result<LocalKeys, RustersError> {
+    let tmp = blake2b_mac::<Blake2bMac<U56>>(
+        key, &[b"paseto-encryption-key", nonce]
+    )?;
+    let auth = blake2b_mac::<Blake2bMac<U32>>(
+        key, &[b"paseto-auth-key-for-aead", nonce]
+    )?;
+    Ok((tmp[..32].to_vec(), tmp[32..].to_vec(), auth))
+}
+pub(crate) fn encrypt_with_nonce(
+    key: &[u8; 32], nonce: &[u8; 32], message: &[u8], footer: &[u8], implicit: &[u8]
+) -> Result<String, RustersError> {
+    let (enc_key, enc_nonce, auth_key) = local_keys(key, nonce)?;
+    let mut cipher = message.to_vec();
+    XChaCha20::new_from_slices(&enc_key, &enc_nonce)
+        .map_err(|_| RustersError::NoSigningKeyError)?
+        .apply_keystream(&mut cipher);
+    let pre_auth = pae(&[
+        LOCAL_HEADER.as_bytes(), nonce, &cipher, footer, implicit,
+    ]);
+    let tag = blake2b_mac::<Blake2bMac<U32>>(&auth_key, &[&pre_auth])?;
+    let body = [nonce.as_slice(), &cipher, &tag].concat();
+    Ok(append_footer(format!("{}{}", LOCAL_HEADER, encode(&body)), footer))
+}
+/// Encrypts `message` as a `v4.local` token
+pub fn encrypt(
+    key: &[u8; 32], message: &[u8], footer: &[u8], implicit: &[u8]
+) -> Result<String, RustersError> {
+    let mut nonce = [0_u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut nonce);
+    encrypt_with_nonce(key, &nonce, message, footer, implicit)
+}
+/// Authenticates and decrypts a `v4.local` token, returning the message and
+/// footer
+pub fn decrypt<'a>(
+    key: &[u8; 32], token: &'a str, implicit: &[u8]
+) -> Result<(Vec<u8>, Vec<u8>), RustersError> {
+    let (body, footer) = split(token, LOCAL_HEADER)?;
+    if body.len() < 64 {
+        return Err(RustersError::InvalidTokenError);
+    }
+    let (nonce, rest) = body.split_at(32);
+    let (cipher, tag) = rest.split_at(rest.len() - 32);
+    let (enc_key, enc_nonce, auth_key) = local_keys(key, nonce)?;
+    let pre_auth = pae(&[
+        LOCAL_HEADER.as_bytes(), nonce, cipher, &footer, implicit,
+    ]);
+    let mut mac = <Blake2bMac<U32> as blake2::digest::KeyInit>::new_from_slice(&auth_key)
+        .map_err(|_| RustersError::NoSigningKeyError)?;
+    mac.update(&pre_auth);
+    mac.verify_slice(tag).map_err(|_| RustersError::InvalidTokenError)?;
+    let mut message = cipher.to_vec();
+    XChaCha20::new_from_slices(&enc_key, &enc_nonce)
+        .map_err(|_| RustersError::NoSigningKeyError)?
+        .apply_keystream(&mut message);
+    Ok((message, footer))
+}
+/// Signs `message` as a `v4.public` token
+pub fn sign(
+    key: &SigningKey, message: &[u8], footer: &[u8], implicit: &[u8]
+) -> String {
+    let pre_auth = pae(&[PUBLIC_HEADER.as_bytes(), message, footer, implicit]);
+    let signature = key.sign(&pre_auth).to_bytes();
+    let body = [message, signature.as_slice()].concat();
+    append_footer(format!("{}{}", PUBLIC_HEADER, encode(&body)), footer)
+}
+/// Verifies a `v4.public` token, returning the message and footer
+pub fn verify<'a>(
+    key: &VerifyingKey, token: &'a str, implicit: &[u8]
+) -> Result<(Vec<u8>, Vec<u8>), RustersError> {
+    let (body, footer) = split(token, PUBLIC_HEADER)?;
+    if body.len() < 64 {
+        return Err(RustersError::InvalidTokenError);
+    }
+    let (message, signature) = body.split_at(body.len() - 64);
+    let signature = Signature::from_slice(signature)
+        .map_err(|_| RustersError::InvalidTokenError)?;
+    let pre_auth = pae(&[PUBLIC_HEADER.as_bytes(), message, &footer, implicit]);
+    key.verify(&pre_auth, &signature)
+        .map_err(|_| RustersError::InvalidTokenError)?;
+    Ok((message.to_vec(), footer))
+}
+#[derive(Deserialize, Serialize)]
+struct Footer {
+    kid: String,
+}
+#[derive(Deserialize, Serialize)]
+struct Claims {
+    sub: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sid: Option<i64>,
+    iat: DateTime<Utc>,
+    nbf: DateTime<Utc>,
+    exp: DateTime<Utc>,
+}
+/// The checked claims of a PASETO v4 token issued for a `User`
+pub struct Paseto {
+    subject: String,
+    session_pk: Option<i64>,
+    issued_dt: DateTime<Utc>,
+    not_before_dt: DateTime<Utc>,
+    expired_dt: DateTime<Utc>,
+    key_id: String,
+}
+impl Paseto {
+    /// The primary key of the `User` the token was issued to, as a string
+    pub fn get_subject(&self) -> String {
+        self.subject.clone()
+    }
+    pub fn get_session_pk(&self) -> Option<i64> {
+        self.session_pk
+    }
+    pub fn get_issued_dt(&self) -> DateTime<Utc> {
+        self.issued_dt
+    }
+    pub fn get_not_before_dt(&self) -> DateTime<Utc> {
+        self.not_before_dt
+    }
+    pub fn get_expired_dt(&self) -> DateTime<Utc> {
+        self.expired_dt
+    }
+    pub fn get_key_id(&self) -> String {
+        self.key_id.clone()
+    }
+    pub fn default_expires() -> Duration {
+        Duration::minutes(15)
+    }
+    /// Issues a token for `user`, tied to `session` when one is given, with the
+    /// key id in the footer. Local keys encrypt the claims, public keys sign
+    /// them
+    pub fn issue(
+        key: &PasetoKey,
+        user: &User,
+        session: Option<&Session>,
+        expires: Option<Duration>,
+    ) -> Result<String, RustersError> {
+        let now = Utc::now();
+        let claims = serde_json::to_vec(&Claims {
+            sub: user.get_pk().to_string(),
+            sid: session.map(|s| s.get_pk()),
+            iat: now,
+            nbf: now,
+            exp: now + expires.unwrap_or_else(Self::default_expires),
+        }).map_err(|_| RustersError::InvalidTokenError)?;
+        let footer = serde_json::to_vec(&Footer { kid: key.get_kid().to_string() })
+            .map_err(|_| RustersError::InvalidTokenError)?;
+        match key {
+            PasetoKey::Local { key, .. } => encrypt(key, &claims, &footer, &[]),
+            PasetoKey::Public { key, .. } => Ok(sign(key, &claims, &footer, &[])),
+            PasetoKey::Verifying { .. } => Err(RustersError::NoSigningKeyError),
+        }
+    }
+    /// Checks `token` with the key named in its footer, which must be of the
+    /// purpose given by the token header, then checks `nbf` and `exp`
+    pub fn validate<'a>(
+        keys: &[PasetoKey], token: &'a str
+    ) -> Result<Self, RustersError> {
+        let token = token.trim();
+        let footer = match token.rsplit_once('.') {
+            Some((_, footer)) if token.matches('.').count() == 3 => decode(footer)?,
+            _ => return Err(RustersError::InvalidTokenError),
+        };
+        let kid = serde_json::from_slice::<Footer>(&footer)
+            .map_err(|_| RustersError::InvalidTokenError)?
+            .kid;
+        let key = keys.iter()
+            .find(|k| k.get_kid() == kid && token.starts_with(k.get_header()))
+            .ok_or(RustersError::InvalidTokenError)?;
+        let (message, _) = match key {
+            PasetoKey::Local { key, .. } => decrypt(key, token, &[])?,
+            PasetoKey::Public { key, .. } => verify(&key.verifying_key(), token, &[])?,
+            PasetoKey::Verifying { key, .. } => verify(key, token, &[])?,
+        };
+        let claims = serde_json::from_slice::<Claims>(&message)
+            .map_err(|_| RustersError::InvalidTokenError)?;
+        let now = Utc::now();
+        if claims.exp <= now || claims.nbf > now {
+            return Err(RustersError::InvalidTokenError);
+        }
+        Ok(Paseto {
+            subject: claims.sub,
+            session_pk: claims.sid,
+            issued_dt: claims.iat,
+            not_before_dt: claims.nbf,
+            expired_dt: claims.exp,
+            key_id: kid,
+        })
+    }
+}
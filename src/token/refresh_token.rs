@@ -0,0 +1,262 @@
+use {
+    chrono::{
+        DateTime,
+        Duration,
+        Utc,
+    },
+    crate::{
+        error::{
+            MatchRustersError,
+            RustersError,
+        },
+        token::Token,
+        user::User,
+    },
+    sqlx::{ FromRow, SqlitePool, query, query_as, },
+};
+/// One rotation of a refresh token family: a long lived refresh `Token` and
+/// the short lived access `Token` issued with it. Refreshing retires the row
+/// and adds the next one to the family; a retired refresh token presented again
+/// revokes the whole family
+#[derive(FromRow)]
+pub struct RefreshToken {
+    pk: i64,
+    family_pk: i64,
+    user_pk: i64,
+    token_pk: i64,
+    access_token_pk: i64,
+    is_retired: bool,
+    created_dt: DateTime<Utc>,
+}
+impl RefreshToken {
+    pub fn get_pk(&self) -> i64 {
+        self.pk
+    }
+    pub fn get_family_pk(&self) -> i64 {
+        self.family_pk
+    }
+    pub fn get_user_pk(&self) -> i64 {
+        self.user_pk
+    }
+    pub fn get_token_pk(&self) -> i64 {
+        self.token_pk
+    }
+    pub fn get_access_token_pk(&self) -> i64 {
+        self.access_token_pk
+    }
+    pub fn get_is_retired(&self) -> bool {
+        self.is_retired
+    }
+    pub fn get_created_dt(&self) -> DateTime<Utc> {
+        self.created_dt
+    }
+    pub fn default_access_expires() -> Duration {
+        Duration::minutes(15)
+    }
+    pub fn default_refresh_expires() -> Duration {
+        Duration::days(30)
+    }
+    pub async fn lookup_by_pk(
+        db: &SqlitePool, pk: i64
+    ) -> Result<Self, RustersError> {
+        query_as::<_, Self>("
+            select
+                r.pk,
+                r.family_pk,
+                f.user_pk,
+                r.token_pk,
+                r.access_token_pk,
+                r.is_retired,
+                r.created_dt
+            from RefreshTokens as r
+            join RefreshTokenFamilies as f
+            on f.pk = r.family_pk
+            where r.pk = $1"
+        ).bind(pk)
+            .fetch_one(db)
+            .await
+            .quick_match()
+    }
+    /// The rotation `hash` was issued in, whether or not it has expired or
+    /// been retired, as long as its family has not been revoked
+    pub async fn lookup<'a>(
+        db: &SqlitePool, hash: &'a str
+    ) -> Result<Self, RustersError> {
+        query_as::<_, Self>("
+            select
+                r.pk,
+                r.family_pk,
+                f.user_pk,
+                r.token_pk,
+                r.access_token_pk,
+                r.is_retired,
+                r.created_dt
+            from RefreshTokens as r
+            join RefreshTokenFamilies as f
+            on f.pk = r.family_pk
+            join Tokens as t
+            on t.pk = r.token_pk
+            where t.hash = $1
+            and f.is_active = 1"
+        ).bind(hash)
+            .fetch_one(db)
+            .await
+            .quick_match()
+    }
+    async fn insert(
+        db: &SqlitePool,
+        family_pk: i64,
+        access_expires: Option<Duration>,
+        refresh_expires: Option<Duration>,
+    ) -> Result<(Token, Token), RustersError> {
+        let access = Token::basic(
+            db,
+            Some(access_expires.unwrap_or_else(Self::default_access_expires)),
+        ).await?;
+        let refresh = Token::basic(
+            db,
+            Some(refresh_expires.unwrap_or_else(Self::default_refresh_expires)),
+        ).await?;
+        query("
+            insert into RefreshTokens (
+                family_pk,
+                token_pk,
+                access_token_pk,
+                is_retired,
+                created_dt
+            ) values (
+                $1,
+                $2,
+                $3,
+                $4,
+                $5
+            )"
+        ).bind(family_pk)
+            .bind(refresh.get_pk())
+            .bind(access.get_pk())
+            .bind(0_i64)
+            .bind(Utc::now())
+            .execute(db)
+            .await
+            .quick_match()?;
+        Ok((access, refresh))
+    }
+    /// Starts a new family for `user`, returning its first access and refresh
+    /// tokens
+    pub async fn issue(
+        db: &SqlitePool,
+        user: &User,
+        access_expires: Option<Duration>,
+        refresh_expires: Option<Duration>,
+    ) -> Result<(Token, Token), RustersError> {
+        let family_pk = query("
+            insert into RefreshTokenFamilies (
+                user_pk,
+                is_active,
+                created_dt
+            ) values (
+                $1,
+                $2,
+                $3
+            )"
+        ).bind(user.get_pk())
+            .bind(1_i64)
+            .bind(Utc::now())
+            .execute(db)
+            .await
+            .quick_match()?
+            .last_insert_rowid();
+        Self::insert(db, family_pk, access_expires, refresh_expires).await
+    }
+    /// Trades the refresh token `hash` for a new access and refresh token in
+    /// the same family. Presenting a refresh token which was already traded
+    /// revokes the family and fails with `TokenReuseError`
+    pub async fn refresh<'a>(
+        db: &SqlitePool,
+        hash: &'a str,
+        access_expires: Option<Duration>,
+        refresh_expires: Option<Duration>,
+    ) -> Result<(Token, Token), RustersError> {
+        let current = Self::lookup(db, hash)
+            .await
+            .map_err(|_| RustersError::InvalidTokenError)?;
+        if current.is_retired {
+            Self::revoke_family(db, current.family_pk).await?;
+            return Err(RustersError::TokenReuseError);
+        }
+        let token = Token::lookup_by_pk(db, current.token_pk)
+            .await
+            .map_err(|_| RustersError::InvalidTokenError)?;
+        let retired = query("
+            update RefreshTokens
+            set is_retired = 1
+            where pk = $1
+            and is_retired = 0"
+        ).bind(current.pk)
+            .execute(db)
+            .await
+            .quick_match()?
+            .rows_affected() > 0;
+        if !retired {
+            Self::revoke_family(db, current.family_pk).await?;
+            return Err(RustersError::TokenReuseError);
+        }
+        token.expire(db).await?;
+        Self::insert(db, current.family_pk, access_expires, refresh_expires).await
+    }
+    /// The user an unexpired access token was issued to
+    pub async fn authenticate<'a>(
+        db: &SqlitePool, access: &'a str
+    ) -> Result<User, RustersError> {
+        let user_pk = query_as::<_, (i64,)>("
+            select f.user_pk
+            from RefreshTokens as r
+            join RefreshTokenFamilies as f
+            on f.pk = r.family_pk
+            join Tokens as t
+            on t.pk = r.access_token_pk
+            where t.hash = $1
+            and t.expired_dt > $2
+            and f.is_active = 1"
+        ).bind(access)
+            .bind(Utc::now())
+            .fetch_one(db)
+            .await
+            .map_err(|_| RustersError::InvalidTokenError)?
+            .0;
+        User::lookup_by_pk(db, user_pk).await
+    }
+    /// Expires every access and refresh token ever issued in the family
+    pub async fn revoke_family(
+        db: &SqlitePool, family_pk: i64
+    ) -> Result<(), RustersError> {
+        let now = Utc::now();
+        query("
+            update Tokens
+            set expired_dt = $1
+            where expired_dt > $1
+            and pk in (
+                select token_pk
+                from RefreshTokens
+                where family_pk = $2
+                union
+                select access_token_pk
+                from RefreshTokens
+                where family_pk = $2
+            )"
+        ).bind(now)
+            .bind(family_pk)
+            .execute(db)
+            .await
+            .quick_match()?;
+        query("
+            update RefreshTokenFamilies
+            set is_active = 0
+            where pk = $1"
+        ).bind(family_pk)
+            .execute(db)
+            .await
+            .quick_match()?;
+        Ok(())
+    }
+}
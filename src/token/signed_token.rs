@@ -0,0 +1,152 @@
+use {
+    base64::URL_SAFE_NO_PAD,
+    chrono::{
+        DateTime,
+        Duration,
+        TimeZone,
+        Utc,
+    },
+    crate::{
+        error::RustersError,
+        keyring::Keyring,
+        session::Session,
+        token::Token,
+        user::User,
+    },
+    hmac::{
+        Hmac,
+        Mac,
+    },
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+    sha3::Sha3_256,
+    sqlx::SqlitePool,
+};
+#[derive(Deserialize, Serialize)]
+struct Claims {
+    sub: String,
+    sid: i64,
+    iat: i64,
+    exp: i64,
+    kid: String,
+}
+/// A token carrying its own claims, signed with HMAC-SHA3-256 by a key from a
+/// `Keyring`, so it can be checked without a database round trip. It is
+/// presented as the base64 encoded claims and signature joined by a `.`
+pub struct SignedToken {
+    subject: String,
+    session_pk: i64,
+    issued_dt: DateTime<Utc>,
+    expired_dt: DateTime<Utc>,
+    key_id: String,
+}
+impl SignedToken {
+    /// The primary key of the `User` the token was issued to, as a string
+    pub fn get_subject(&self) -> String {
+        self.subject.clone()
+    }
+    pub fn get_session_pk(&self) -> i64 {
+        self.session_pk
+    }
+    pub fn get_issued_dt(&self) -> DateTime<Utc> {
+        self.issued_dt
+    }
+    pub fn get_expired_dt(&self) -> DateTime<Utc> {
+        self.expired_dt
+    }
+    pub fn get_key_id(&self) -> String {
+        self.key_id.clone()
+    }
+    /// Kept short as a signed token can't be revoked unless checked with
+    /// `verify_unrevoked`
+    pub fn default_expires() -> Duration {
+        Duration::minutes(5)
+    }
+    fn mac<'a>(secret: &[u8], payload: &'a str) -> Result<Hmac<Sha3_256>, RustersError> {
+        let mut mac = Hmac::<Sha3_256>::new_from_slice(secret)
+            .map_err(|_| RustersError::NoSigningKeyError)?;
+        mac.update(payload.as_bytes());
+        Ok(mac)
+    }
+    /// Signs a token for `user` logged in on `session` with the active key
+    pub fn issue(
+        keyring: &Keyring, session: &Session, user: &User, expires: Option<Duration>
+    ) -> Result<String, RustersError> {
+        let (kid, secret) = match keyring.active() {
+            Some(active) => active,
+            None => return Err(RustersError::NoSigningKeyError),
+        };
+        let now = Utc::now();
+        let exp = now + expires.unwrap_or_else(Self::default_expires);
+        let claims = Claims {
+            sub: user.get_pk().to_string(),
+            sid: session.get_pk(),
+            iat: now.timestamp(),
+            exp: exp.timestamp(),
+            kid: kid.to_string(),
+        };
+        let json = serde_json::to_vec(&claims)
+            .map_err(|_| RustersError::InvalidTokenError)?;
+        let payload = base64::encode_config(json, URL_SAFE_NO_PAD);
+        let signature = Self::mac(secret, &payload)?.finalize().into_bytes();
+        Ok(format!(
+            "{}.{}",
+            payload,
+            base64::encode_config(signature, URL_SAFE_NO_PAD)
+        ))
+    }
+    /// Checks the signature and expiry of `presented` against `keyring` alone
+    pub fn verify<'a>(
+        keyring: &Keyring, presented: &'a str
+    ) -> Result<Self, RustersError> {
+        let (payload, signature) = match presented.trim().split_once('.') {
+            Some(parts) => parts,
+            None => return Err(RustersError::InvalidTokenError),
+        };
+        let json = base64::decode_config(payload, URL_SAFE_NO_PAD)
+            .map_err(|_| RustersError::InvalidTokenError)?;
+        let signature = base64::decode_config(signature, URL_SAFE_NO_PAD)
+            .map_err(|_| RustersError::InvalidTokenError)?;
+        let claims = serde_json::from_slice::<Claims>(&json)
+            .map_err(|_| RustersError::InvalidTokenError)?;
+        let secret = match keyring.get(&claims.kid) {
+            Some(secret) => secret,
+            None => return Err(RustersError::InvalidTokenError),
+        };
+        Self::mac(secret, payload)?
+            .verify_slice(&signature)
+            .map_err(|_| RustersError::InvalidTokenError)?;
+        let issued_dt = Utc.timestamp_opt(claims.iat, 0)
+            .single()
+            .ok_or(RustersError::InvalidTokenError)?;
+        let expired_dt = Utc.timestamp_opt(claims.exp, 0)
+            .single()
+            .ok_or(RustersError::InvalidTokenError)?;
+        if expired_dt <= Utc::now() {
+            return Err(RustersError::InvalidTokenError);
+        }
+        Ok(SignedToken {
+            subject: claims.sub,
+            session_pk: claims.sid,
+            issued_dt,
+            expired_dt,
+            key_id: claims.kid,
+        })
+    }
+    /// As `verify`, then also checks that the session the token was issued
+    /// for has not since expired or been ended
+    pub async fn verify_unrevoked<'a>(
+        db: &SqlitePool, keyring: &Keyring, presented: &'a str
+    ) -> Result<Self, RustersError> {
+        let signed = Self::verify(keyring, presented)?;
+        let session = Session::lookup_by_pk(db, signed.session_pk)
+            .await
+            .map_err(|_| RustersError::InvalidTokenError)?;
+        Token::lookup_by_pk(db, session.get_token_pk())
+            .await
+            .map_err(|_| RustersError::InvalidTokenError)?;
+        Ok(signed)
+    }
+}
@@ -1,3 +1,12 @@
+pub mod api_key;
+pub mod email_change;
+pub mod email_verification;
+pub mod login_attempt;
+pub mod magic_link;
+pub mod password_history;
+pub mod password_policy;
+pub mod recovery_code;
+pub mod totp;
 use {
     chrono::{
         DateTime,
@@ -13,8 +22,23 @@ use {
             Hash,
         },
     },
+    login_attempt::{
+        LoginAttempt,
+        LoginPolicy,
+    },
+    password_history::PasswordHistory,
+    password_policy::{
+        PasswordPolicy,
+        PolicyViolation,
+    },
     sqlx::{ FromRow, SqlitePool, query, query_as, },
 };
+/// A `Secure` hash of a random value, verified against when no user matches so
+/// that unknown usernames take as long to reject as bad passwords
+pub(crate) const DUMMY_PASSWORD_HASH: &str = "JDJiJDEyJGhrMk1QYnVWVXhXNTVST1ZmWVJtOE9xYWpGUlgvVDFFWjM0OGp5RTU4aFYyVk1sWmNPd1Jh";
+/// Stored in place of a password hash for passwordless users. It is not a
+/// `Secure` hash, so no password can ever match it
+const UNUSABLE_PASSWORD_HASH: &str = "!";
 #[derive(FromRow)]
 pub struct User {
     pk: i64,
@@ -23,6 +47,8 @@ pub struct User {
     salt: String,
     is_active: bool,
     created_dt: DateTime<Utc>,
+    email: Option<String>,
+    email_verified_dt: Option<DateTime<Utc>>,
 }
 impl User {
     pub fn get_pk(&self) -> i64 {
@@ -43,6 +69,15 @@ impl User {
     pub fn get_created_dt(&self) -> DateTime<Utc> {
         self.created_dt
     }
+    pub fn get_email(&self) -> Option<String> {
+        self.email.clone()
+    }
+    pub fn get_email_verified_dt(&self) -> Option<DateTime<Utc>> {
+        self.email_verified_dt
+    }
+    pub fn get_is_email_verified(&self) -> bool {
+        self.email.is_some() && self.email_verified_dt.is_some()
+    }
     pub async fn lookup_by_pk(
         db: &SqlitePool, pk: i64
     ) -> Result<Self, RustersError> {
@@ -53,7 +88,9 @@ impl User {
                 password_hash,
                 salt,
                 is_active,
-                created_dt
+                created_dt,
+                email,
+                email_verified_dt
             from Users
             where pk = $1
             and is_active = 1;"
@@ -72,7 +109,9 @@ impl User {
                 password_hash,
                 salt,
                 is_active,
-                created_dt
+                created_dt,
+                email,
+                email_verified_dt
             from Users
             where username = $1
             and is_active = 1;"
@@ -84,9 +123,35 @@ impl User {
     pub async fn insert<'a>(
         db: &SqlitePool, username: &'a str, password: &'a str
     ) -> Result<Self, RustersError> {
+        Self::insert_with_policy(
+            db, username, password, &PasswordPolicy::default()
+        ).await
+    }
+    /// Inserts a user who can't log in with a password, only by other means
+    /// such as a `MagicLink`, until one is set with `change_password`
+    pub async fn insert_passwordless<'a>(
+        db: &SqlitePool, username: &'a str
+    ) -> Result<Self, RustersError> {
+        Self::insert_hashed(db, username, UNUSABLE_PASSWORD_HASH, "").await
+    }
+    pub async fn insert_with_policy<'a>(
+        db: &SqlitePool,
+        username: &'a str,
+        password: &'a str,
+        policy: &PasswordPolicy,
+    ) -> Result<Self, RustersError> {
+        policy.enforce(username, password)?;
         let hashed = Secure::from_string(password)?;
-        let salt = hashed.get_salt();
-        let hash = hashed.get_hash();
+        let user = Self::insert_hashed(
+            db, username, &hashed.get_hash(), &hashed.get_salt()
+        ).await?;
+        PasswordHistory::insert(db, &user).await?;
+        PasswordHistory::trim(db, &user, policy.history_depth).await?;
+        Ok(user)
+    }
+    async fn insert_hashed<'a>(
+        db: &SqlitePool, username: &'a str, hash: &'a str, salt: &'a str
+    ) -> Result<Self, RustersError> {
         let pk = query("
             insert into Users (
                 username,
@@ -112,17 +177,128 @@ impl User {
             .last_insert_rowid();
         Self::lookup_by_pk(db, pk).await
     }
-    pub async fn validate<'a>(
+    /// Sets the email address of the user, which starts out unverified
+    pub async fn set_email<'a>(
+        &mut self, db: &SqlitePool, email: &'a str
+    ) -> Result<(), RustersError> {
+        query("
+            update Users
+            set email = $1,
+                email_verified_dt = null
+            where pk = $2"
+        ).bind(email)
+            .bind(self.pk)
+            .execute(db)
+            .await
+            .quick_match()?;
+        self.email = Some(email.to_string());
+        self.email_verified_dt = None;
+        Ok(())
+    }
+    pub async fn change_password<'a>(
+        &mut self, db: &SqlitePool, password: &'a str
+    ) -> Result<(), RustersError> {
+        self.change_password_with_policy(
+            db, password, &PasswordPolicy::default()
+        ).await
+    }
+    /// Replaces the password of the user, whether changed by the user or
+    /// reset on their behalf, refusing any of their recent passwords
+    pub async fn change_password_with_policy<'a>(
+        &mut self, db: &SqlitePool, password: &'a str, policy: &PasswordPolicy
+    ) -> Result<(), RustersError> {
+        let mut violations = policy.check(&self.username, password)?;
+        let reused = PasswordHistory::is_reused(
+            db, self, password, policy.history_depth
+        ).await?;
+        if reused {
+            violations.push(PolicyViolation::Reused);
+        }
+        if !violations.is_empty() {
+            return Err(RustersError::WeakPassword(violations));
+        }
+        let hashed = Secure::from_string(password)?;
+        let salt = hashed.get_salt();
+        let hash = hashed.get_hash();
+        query("
+            update Users
+            set password_hash = $1,
+                salt = $2
+            where pk = $3"
+        ).bind(&hash)
+            .bind(&salt)
+            .bind(self.pk)
+            .execute(db)
+            .await
+            .quick_match()?;
+        self.password_hash = hash;
+        self.salt = salt;
+        PasswordHistory::insert(db, self).await?;
+        PasswordHistory::trim(db, self, policy.history_depth).await?;
+        Ok(())
+    }
+    async fn check_credentials<'a>(
         db: &SqlitePool, username: &'a str, password: &'a str
     ) -> Result<Self, RustersError> {
         let user = match Self::lookup(db, username).await {
             Ok(user) => user,
-            Err(_) => return Err(RustersError::InvalidCredentialsError),
+            Err(_) => {
+                Secure::validate(password, DUMMY_PASSWORD_HASH)?;
+                return Err(RustersError::InvalidCredentialsError);
+            },
         };
+        if user.password_hash == UNUSABLE_PASSWORD_HASH {
+            Secure::validate(password, DUMMY_PASSWORD_HASH)?;
+            return Err(RustersError::InvalidCredentialsError);
+        }
         if Secure::validate(password, &user.password_hash)? {
             Ok(user)
         } else {
             Err(RustersError::InvalidCredentialsError)
         }
     }
+    /// Validates credentials without recording failures or honoring
+    /// lockouts, which `validate_with_policy` opts into
+    pub async fn validate<'a>(
+        db: &SqlitePool, username: &'a str, password: &'a str
+    ) -> Result<Self, RustersError> {
+        Self::check_credentials(db, username, password).await
+    }
+    /// Validates credentials, recording failures against `username` (and
+    /// `client_key` when the policy counts per client) and refusing to check
+    /// them at all while the account is locked
+    pub async fn validate_with_policy<'a>(
+        db: &SqlitePool,
+        username: &'a str,
+        password: &'a str,
+        client_key: Option<&'a str>,
+        policy: &LoginPolicy,
+    ) -> Result<Self, RustersError> {
+        let locked = LoginAttempt::locked_until(
+            db, username, client_key, policy
+        ).await?;
+        if let Some(until) = locked {
+            return Err(RustersError::AccountLocked { until });
+        }
+        match Self::check_credentials(db, username, password).await {
+            Ok(user) => {
+                LoginAttempt::succeed(db, username, client_key, policy).await?;
+                Ok(user)
+            },
+            Err(RustersError::InvalidCredentialsError) => {
+                let locked = LoginAttempt::fail(
+                    db, username, client_key, policy
+                ).await?;
+                match locked {
+                    Some(until) => Err(RustersError::AccountLocked { until }),
+                    None => Err(RustersError::InvalidCredentialsError),
+                }
+            },
+            Err(e) => Err(e),
+        }
+    }
+    /// Lifts any lockout on the user and forgets their failed logins
+    pub async fn unlock(&self, db: &SqlitePool) -> Result<(), RustersError> {
+        LoginAttempt::clear(db, &self.username).await
+    }
 }
@@ -0,0 +1,262 @@
+use {
+    chrono::{
+        DateTime,
+        Duration,
+        Utc,
+    },
+    crate::{
+        error::{
+            MatchRustersError,
+            RustersError,
+        },
+        hash::{
+            Basic,
+            Hash,
+            Secure,
+        },
+        token::Token,
+        user::User,
+    },
+    rand::Rng,
+    sqlx::{ FromRow, SqlitePool, query, query_as, },
+};
+const PREFIX_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+/// A personal key a `User` scripts against services with. The key is presented
+/// as `rk_<prefix>_<secret>`; the prefix is stored as is so it can be shown in
+/// UIs, while the secret is kept as a `Secure` hash in a `Token` which also
+/// carries the expiry and scopes of the key
+#[derive(FromRow)]
+pub struct ApiKey {
+    pk: i64,
+    user_pk: i64,
+    token_pk: i64,
+    name: String,
+    prefix: String,
+    last_used_dt: Option<DateTime<Utc>>,
+    expired_dt: DateTime<Utc>,
+    is_active: bool,
+    created_dt: DateTime<Utc>,
+}
+impl ApiKey {
+    const MARKER: &'static str = "rk_";
+    const PREFIX_LEN: usize = 8;
+    pub fn get_pk(&self) -> i64 {
+        self.pk
+    }
+    pub fn get_user_pk(&self) -> i64 {
+        self.user_pk
+    }
+    pub fn get_token_pk(&self) -> i64 {
+        self.token_pk
+    }
+    pub fn get_name(&self) -> String {
+        self.name.clone()
+    }
+    pub fn get_prefix(&self) -> String {
+        self.prefix.clone()
+    }
+    pub fn get_last_used_dt(&self) -> Option<DateTime<Utc>> {
+        self.last_used_dt
+    }
+    pub fn get_expired_dt(&self) -> DateTime<Utc> {
+        self.expired_dt
+    }
+    pub fn get_is_active(&self) -> bool {
+        self.is_active
+    }
+    pub fn get_created_dt(&self) -> DateTime<Utc> {
+        self.created_dt
+    }
+    /// How long a key created without an expiry lasts
+    pub fn never_expires() -> Duration {
+        Duration::days(365 * 100)
+    }
+    pub async fn lookup_by_pk(
+        db: &SqlitePool, pk: i64
+    ) -> Result<Self, RustersError> {
+        query_as::<_, Self>("
+            select
+                k.pk,
+                k.user_pk,
+                k.token_pk,
+                k.name,
+                k.prefix,
+                k.last_used_dt,
+                t.expired_dt,
+                k.is_active,
+                k.created_dt
+            from ApiKeys as k
+            join Tokens as t
+            on t.pk = k.token_pk
+            where k.pk = $1"
+        ).bind(pk)
+            .fetch_one(db)
+            .await
+            .quick_match()
+    }
+    /// The active, unexpired key with `prefix`
+    pub async fn lookup<'a>(
+        db: &SqlitePool, prefix: &'a str
+    ) -> Result<Self, RustersError> {
+        query_as::<_, Self>("
+            select
+                k.pk,
+                k.user_pk,
+                k.token_pk,
+                k.name,
+                k.prefix,
+                k.last_used_dt,
+                t.expired_dt,
+                k.is_active,
+                k.created_dt
+            from ApiKeys as k
+            join Tokens as t
+            on t.pk = k.token_pk
+            where k.prefix = $1
+            and k.is_active = 1
+            and t.expired_dt > $2"
+        ).bind(prefix)
+            .bind(Utc::now())
+            .fetch_one(db)
+            .await
+            .quick_match()
+    }
+    /// Keys of `user` which have not been revoked, newest first
+    pub async fn list(
+        db: &SqlitePool, user: &User
+    ) -> Result<Vec<Self>, RustersError> {
+        query_as::<_, Self>("
+            select
+                k.pk,
+                k.user_pk,
+                k.token_pk,
+                k.name,
+                k.prefix,
+                k.last_used_dt,
+                t.expired_dt,
+                k.is_active,
+                k.created_dt
+            from ApiKeys as k
+            join Tokens as t
+            on t.pk = k.token_pk
+            where k.user_pk = $1
+            and k.is_active = 1
+            order by k.pk desc"
+        ).bind(user.get_pk())
+            .fetch_all(db)
+            .await
+            .quick_match()
+    }
+    fn random_prefix() -> String {
+        let mut rng = rand::thread_rng();
+        (0..Self::PREFIX_LEN)
+            .map(|_| PREFIX_ALPHABET[rng.gen_range(0..PREFIX_ALPHABET.len())] as char)
+            .collect()
+    }
+    /// Creates a key for `user`, returning it and the full key, which is only
+    /// available now and must be shown to the user
+    pub async fn create<'a>(
+        db: &SqlitePool,
+        user: &User,
+        name: &'a str,
+        expires: Option<Duration>,
+        scopes: &[&'a str],
+    ) -> Result<(Self, String), RustersError> {
+        let prefix = Self::random_prefix();
+        let secret = Basic::rand()?.get_hash();
+        let token = Token::insert_with_scopes(
+            db,
+            Secure::from_string(&secret)?,
+            Some(expires.unwrap_or_else(Self::never_expires)),
+            scopes,
+        ).await?;
+        let pk = query("
+            insert into ApiKeys (
+                user_pk,
+                token_pk,
+                name,
+                prefix,
+                is_active,
+                created_dt
+            ) values (
+                $1,
+                $2,
+                $3,
+                $4,
+                $5,
+                $6
+            )"
+        ).bind(user.get_pk())
+            .bind(token.get_pk())
+            .bind(name)
+            .bind(&prefix)
+            .bind(1_i64)
+            .bind(Utc::now())
+            .execute(db)
+            .await
+            .quick_match()?
+            .last_insert_rowid();
+        let key = Self::lookup_by_pk(db, pk).await?;
+        Ok((key, format!("{}{}_{}", Self::MARKER, prefix, secret)))
+    }
+    /// Checks a presented key, recording its use and returning the user it
+    /// belongs to along with the key
+    pub async fn authenticate<'a>(
+        db: &SqlitePool, presented: &'a str
+    ) -> Result<(User, Self), RustersError> {
+        let parts = presented.trim()
+            .strip_prefix(Self::MARKER)
+            .and_then(|rest| rest.split_once('_'));
+        let (prefix, secret) = match parts {
+            Some(parts) => parts,
+            None => return Err(RustersError::InvalidTokenError),
+        };
+        let mut key = Self::lookup(db, prefix)
+            .await
+            .map_err(|_| RustersError::InvalidTokenError)?;
+        let token = key.lookup_token(db).await?;
+        if !Secure::validate(secret, token.get_hash())? {
+            return Err(RustersError::InvalidTokenError);
+        }
+        let now = Utc::now();
+        query("
+            update ApiKeys
+            set last_used_dt = $1
+            where pk = $2"
+        ).bind(now)
+            .bind(key.pk)
+            .execute(db)
+            .await
+            .quick_match()?;
+        key.last_used_dt = Some(now);
+        let user = User::lookup_by_pk(db, key.user_pk).await?;
+        Ok((user, key))
+    }
+    /// The `Token` holding the hash, expiry and scopes of the key
+    pub async fn lookup_token(
+        &self, db: &SqlitePool
+    ) -> Result<Token, RustersError> {
+        Token::lookup_by_pk(db, self.token_pk)
+            .await
+            .map_err(|_| RustersError::InvalidTokenError)
+    }
+    pub async fn require_scopes<'a>(
+        &self, db: &SqlitePool, scopes: &[&'a str]
+    ) -> Result<(), RustersError> {
+        self.lookup_token(db).await?.require_scopes(db, scopes).await
+    }
+    pub async fn revoke(self, db: &SqlitePool) -> Result<(), RustersError> {
+        query("
+            update ApiKeys
+            set is_active = 0
+            where pk = $1"
+        ).bind(self.pk)
+            .execute(db)
+            .await
+            .quick_match()?;
+        if let Ok(token) = self.lookup_token(db).await {
+            token.expire(db).await?;
+        }
+        Ok(())
+    }
+}
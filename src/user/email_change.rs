@@ -0,0 +1,272 @@
+use {
+    chrono::{
+        DateTime,
+        Duration,
+        Utc,
+    },
+    crate::{
+        error::{
+            MatchRustersError,
+            RustersError,
+        },
+        token::{
+            consumable_token::{
+                consumer::Consumer,
+                ConsumableToken,
+            },
+            Token,
+        },
+        user::User,
+    },
+    sqlx::{ FromRow, Sqlite, SqlitePool, Transaction, query, query_as, },
+};
+/// Told when a confirmed change moves a `User` off of an email address, so
+/// the old address can be warned
+pub trait EmailChangeHook {
+    fn email_changed<'a>(&self, user: &User, old_email: &'a str, new_email: &'a str);
+}
+impl<F: Fn(&User, &str, &str)> EmailChangeHook for F {
+    fn email_changed<'a>(&self, user: &User, old_email: &'a str, new_email: &'a str) {
+        self(user, old_email, new_email)
+    }
+}
+/// A pending move of a `User` to a new email address, applied only once a
+/// `ConsumableToken` sent to the new address is redeemed
+#[derive(FromRow)]
+pub struct EmailChange {
+    pk: i64,
+    user_pk: i64,
+    consumable_token_pk: i64,
+    old_email: Option<String>,
+    new_email: String,
+    is_active: bool,
+    created_dt: DateTime<Utc>,
+}
+impl EmailChange {
+    pub const CONSUMER: &'static str = "email_change";
+    pub fn get_pk(&self) -> i64 {
+        self.pk
+    }
+    pub fn get_user_pk(&self) -> i64 {
+        self.user_pk
+    }
+    pub fn get_consumable_token_pk(&self) -> i64 {
+        self.consumable_token_pk
+    }
+    pub fn get_old_email(&self) -> Option<String> {
+        self.old_email.clone()
+    }
+    pub fn get_new_email(&self) -> String {
+        self.new_email.clone()
+    }
+    pub fn get_is_active(&self) -> bool {
+        self.is_active
+    }
+    pub fn get_created_dt(&self) -> DateTime<Utc> {
+        self.created_dt
+    }
+    pub fn default_expires() -> Duration {
+        Duration::hours(24)
+    }
+    pub async fn lookup_by_pk(
+        db: &SqlitePool, pk: i64
+    ) -> Result<Self, RustersError> {
+        query_as::<_, Self>("
+            select
+                pk,
+                user_pk,
+                consumable_token_pk,
+                old_email,
+                new_email,
+                is_active,
+                created_dt
+            from EmailChanges
+            where pk = $1"
+        ).bind(pk)
+            .fetch_one(db)
+            .await
+            .quick_match()
+    }
+    pub async fn lookup(
+        db: &SqlitePool, consumable: &ConsumableToken
+    ) -> Result<Self, RustersError> {
+        query_as::<_, Self>("
+            select
+                pk,
+                user_pk,
+                consumable_token_pk,
+                old_email,
+                new_email,
+                is_active,
+                created_dt
+            from EmailChanges
+            where consumable_token_pk = $1
+            and is_active = 1"
+        ).bind(consumable.get_pk())
+            .fetch_one(db)
+            .await
+            .quick_match()
+    }
+    /// Changes of `user` still waiting to be confirmed
+    pub async fn lookup_pending(
+        db: &SqlitePool, user: &User
+    ) -> Result<Vec<Self>, RustersError> {
+        query_as::<_, Self>("
+            select
+                c.pk,
+                c.user_pk,
+                c.consumable_token_pk,
+                c.old_email,
+                c.new_email,
+                c.is_active,
+                c.created_dt
+            from EmailChanges as c
+            join ConsumableTokens as ct
+            on ct.pk = c.consumable_token_pk
+            join Tokens as t
+            on t.pk = ct.token_pk
+            where c.user_pk = $1
+            and c.is_active = 1
+            and t.expired_dt > $2"
+        ).bind(user.get_pk())
+            .bind(Utc::now())
+            .fetch_all(db)
+            .await
+            .quick_match()
+    }
+    /// Starts moving `user` to `new_email`, returning the raw token for the
+    /// caller to deliver to the new address. The current address is left in
+    /// place until the token is redeemed
+    pub async fn request<'a>(
+        db: &SqlitePool, user: &User, new_email: &'a str, expires: Option<Duration>
+    ) -> Result<String, RustersError> {
+        let consumer = Consumer::always(db, Self::CONSUMER).await?;
+        let token = Token::basic(
+            db, Some(expires.unwrap_or_else(Self::default_expires))
+        ).await?;
+        let consumable = ConsumableToken::insert(db, &token, &consumer).await?;
+        query("
+            insert into EmailChanges (
+                user_pk,
+                consumable_token_pk,
+                old_email,
+                new_email,
+                is_active,
+                created_dt
+            ) values (
+                $1,
+                $2,
+                $3,
+                $4,
+                $5,
+                $6
+            )"
+        ).bind(user.get_pk())
+            .bind(consumable.get_pk())
+            .bind(user.get_email())
+            .bind(new_email)
+            .bind(1_i64)
+            .bind(Utc::now())
+            .execute(db)
+            .await
+            .quick_match()?;
+        Ok(token.get_hash())
+    }
+    /// Withdraws every pending change of `user`
+    pub async fn cancel(
+        db: &SqlitePool, user: &User
+    ) -> Result<(), RustersError> {
+        let mut tx = db.begin().await.quick_match()?;
+        Self::cancel_in(&mut tx, user.get_pk()).await?;
+        tx.commit().await.quick_match()
+    }
+    async fn cancel_in(
+        tx: &mut Transaction<'_, Sqlite>, user_pk: i64
+    ) -> Result<(), RustersError> {
+        let now = Utc::now();
+        query("
+            update Tokens
+            set expired_dt = $1
+            where expired_dt > $1
+            and pk in (
+                select ct.token_pk
+                from EmailChanges as c
+                join ConsumableTokens as ct
+                on ct.pk = c.consumable_token_pk
+                where c.user_pk = $2
+                and c.is_active = 1
+            )"
+        ).bind(now)
+            .bind(user_pk)
+            .execute(&mut *tx)
+            .await
+            .quick_match()?;
+        query("
+            update EmailChanges
+            set is_active = 0
+            where user_pk = $1
+            and is_active = 1"
+        ).bind(user_pk)
+            .execute(&mut *tx)
+            .await
+            .quick_match()?;
+        Ok(())
+    }
+    /// Consumes `raw`, moving the user to the address it was sent to, which
+    /// counts as verified, and dropping their other pending changes. `hook`
+    /// hears about the address being replaced, if there was one
+    pub async fn confirm<'a>(
+        db: &SqlitePool, raw: &'a str, hook: Option<&dyn EmailChangeHook>
+    ) -> Result<User, RustersError> {
+        let token = match Token::possible(db, raw).await? {
+            Some(token) => token,
+            None => return Err(RustersError::InvalidTokenError),
+        };
+        let consumer = Consumer::always(db, Self::CONSUMER).await?;
+        let consumable = ConsumableToken::lookup(db, &token, &consumer)
+            .await
+            .map_err(|_| RustersError::InvalidTokenError)?;
+        let change = Self::lookup(db, &consumable)
+            .await
+            .map_err(|_| RustersError::InvalidTokenError)?;
+        let now = Utc::now();
+        // claiming the token takes the write lock, so a concurrent confirm of
+        // another change waits and then finds its token cancelled
+        let mut tx = db.begin().await.quick_match()?;
+        let claimed = query("
+            update Tokens
+            set expired_dt = $1
+            where pk = $2
+            and expired_dt > $1"
+        ).bind(now)
+            .bind(token.get_pk())
+            .execute(&mut tx)
+            .await
+            .quick_match()?
+            .rows_affected() == 1;
+        if !claimed {
+            return Err(RustersError::InvalidTokenError);
+        }
+        let old_email = User::lookup_by_pk(db, change.user_pk).await?.get_email();
+        query("
+            update Users
+            set email = $1,
+                email_verified_dt = $2
+            where pk = $3"
+        ).bind(&change.new_email)
+            .bind(now)
+            .bind(change.user_pk)
+            .execute(&mut tx)
+            .await
+            .quick_match()?;
+        Self::cancel_in(&mut tx, change.user_pk).await?;
+        tx.commit().await.quick_match()?;
+        let user = User::lookup_by_pk(db, change.user_pk).await?;
+        if let (Some(hook), Some(old_email)) = (hook, old_email) {
+            if old_email != change.new_email {
+                hook.email_changed(&user, &old_email, &change.new_email);
+            }
+        }
+        Ok(user)
+    }
+}
@@ -0,0 +1,165 @@
+use {
+    chrono::{
+        DateTime,
+        Duration,
+        Utc,
+    },
+    crate::{
+        error::{
+            MatchRustersError,
+            RustersError,
+        },
+        token::{
+            consumable_token::{
+                consumer::Consumer,
+                ConsumableToken,
+            },
+            Token,
+        },
+        user::User,
+    },
+    sqlx::{ FromRow, SqlitePool, query, query_as, },
+};
+/// A request to prove ownership of the email address of a `User`, redeemed
+/// with a `ConsumableToken` of the `EmailVerification::CONSUMER` consumer
+#[derive(FromRow)]
+pub struct EmailVerification {
+    pk: i64,
+    user_pk: i64,
+    consumable_token_pk: i64,
+    email: String,
+    created_dt: DateTime<Utc>,
+}
+impl EmailVerification {
+    pub const CONSUMER: &'static str = "email_verification";
+    pub fn get_pk(&self) -> i64 {
+        self.pk
+    }
+    pub fn get_user_pk(&self) -> i64 {
+        self.user_pk
+    }
+    pub fn get_consumable_token_pk(&self) -> i64 {
+        self.consumable_token_pk
+    }
+    pub fn get_email(&self) -> String {
+        self.email.clone()
+    }
+    pub fn get_created_dt(&self) -> DateTime<Utc> {
+        self.created_dt
+    }
+    pub fn default_expires() -> Duration {
+        Duration::hours(24)
+    }
+    pub async fn lookup_by_pk(
+        db: &SqlitePool, pk: i64
+    ) -> Result<Self, RustersError> {
+        query_as::<_, Self>("
+            select
+                pk,
+                user_pk,
+                consumable_token_pk,
+                email,
+                created_dt
+            from EmailVerifications
+            where pk = $1"
+        ).bind(pk)
+            .fetch_one(db)
+            .await
+            .quick_match()
+    }
+    pub async fn lookup(
+        db: &SqlitePool, consumable: &ConsumableToken
+    ) -> Result<Self, RustersError> {
+        query_as::<_, Self>("
+            select
+                pk,
+                user_pk,
+                consumable_token_pk,
+                email,
+                created_dt
+            from EmailVerifications
+            where consumable_token_pk = $1"
+        ).bind(consumable.get_pk())
+            .fetch_one(db)
+            .await
+            .quick_match()
+    }
+    /// Issues a token verifying the current email address of `user`, returning
+    /// the raw token for the caller to deliver to that address
+    pub async fn issue(
+        db: &SqlitePool, user: &User, expires: Option<Duration>
+    ) -> Result<String, RustersError> {
+        let email = match user.get_email() {
+            Some(email) => email,
+            None => return Err(RustersError::MissingEmailError),
+        };
+        let consumer = Consumer::always(db, Self::CONSUMER).await?;
+        let token = Token::basic(
+            db, Some(expires.unwrap_or_else(Self::default_expires))
+        ).await?;
+        let consumable = ConsumableToken::insert(db, &token, &consumer).await?;
+        query("
+            insert into EmailVerifications (
+                user_pk,
+                consumable_token_pk,
+                email,
+                created_dt
+            ) values (
+                $1,
+                $2,
+                $3,
+                $4
+            )"
+        ).bind(user.get_pk())
+            .bind(consumable.get_pk())
+            .bind(email)
+            .bind(Utc::now())
+            .execute(db)
+            .await
+            .quick_match()?;
+        Ok(token.get_hash())
+    }
+    /// Consumes `raw` and marks the address it was issued for as verified,
+    /// provided the user still has that address
+    pub async fn redeem<'a>(
+        db: &SqlitePool, raw: &'a str
+    ) -> Result<User, RustersError> {
+        let token = match Token::possible(db, raw).await? {
+            Some(token) => token,
+            None => return Err(RustersError::InvalidTokenError),
+        };
+        let consumer = Consumer::always(db, Self::CONSUMER).await?;
+        let consumable = ConsumableToken::lookup(db, &token, &consumer)
+            .await
+            .map_err(|_| RustersError::InvalidTokenError)?;
+        let verification = Self::lookup(db, &consumable).await?;
+        let claimed = query("
+            update Tokens
+            set expired_dt = $1
+            where pk = $2
+            and expired_dt > $1"
+        ).bind(Utc::now())
+            .bind(token.get_pk())
+            .execute(db)
+            .await
+            .quick_match()?
+            .rows_affected() == 1;
+        if !claimed {
+            return Err(RustersError::InvalidTokenError);
+        }
+        let user = User::lookup_by_pk(db, verification.user_pk).await?;
+        if user.get_email().as_deref() != Some(verification.email.as_str()) {
+            return Err(RustersError::InvalidTokenError);
+        }
+        query("
+            update Users
+            set email_verified_dt = $1
+            where pk = $2"
+        ).bind(Utc::now())
+            .bind(user.get_pk())
+            .execute(db)
+            .await
+            .quick_match()?;
+        User::lookup_by_pk(db, user.get_pk()).await
+    }
+}
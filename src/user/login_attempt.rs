@@ -0,0 +1,232 @@
+use {
+    chrono::{
+        DateTime,
+        Duration,
+        Utc,
+    },
+    crate::error::{
+        MatchRustersError,
+        RustersError,
+    },
+    sqlx::{ FromRow, SqlitePool, query, query_as, },
+};
+/// Controls when repeated failed logins lock an account
+#[derive(Clone, Debug)]
+pub struct LoginPolicy {
+    /// Failures allowed within `window` before the account locks
+    pub max_failures: i64,
+    /// How far back failures are counted
+    pub window: Duration,
+    /// Length of the first lockout, doubled for every lockout after it
+    pub lockout: Duration,
+    /// Upper bound on the length of a single lockout
+    pub max_lockout: Duration,
+    /// Count failures per username and client key rather than per username
+    pub per_client: bool,
+}
+impl Default for LoginPolicy {
+    fn default() -> Self {
+        LoginPolicy {
+            max_failures: 5,
+            window: Duration::minutes(15),
+            lockout: Duration::minutes(5),
+            max_lockout: Duration::hours(24),
+            per_client: false,
+        }
+    }
+}
+impl LoginPolicy {
+    /// The lockout applied after `prior` earlier lockouts
+    pub fn lockout_for(&self, prior: i64) -> Duration {
+        let factor = 2_i32.checked_pow(prior.clamp(0, 30) as u32).unwrap_or(i32::MAX);
+        match self.lockout.checked_mul(factor) {
+            Some(d) if d < self.max_lockout => d,
+            _ => self.max_lockout,
+        }
+    }
+}
+#[derive(FromRow)]
+pub struct LoginAttempt {
+    pk: i64,
+    username: String,
+    client_key: Option<String>,
+    is_active: bool,
+    locked_until_dt: Option<DateTime<Utc>>,
+    created_dt: DateTime<Utc>,
+}
+impl LoginAttempt {
+    pub fn get_pk(&self) -> i64 {
+        self.pk
+    }
+    pub fn get_username(&self) -> String {
+        self.username.clone()
+    }
+    pub fn get_client_key(&self) -> Option<String> {
+        self.client_key.clone()
+    }
+    pub fn get_is_active(&self) -> bool {
+        self.is_active
+    }
+    pub fn get_locked_until_dt(&self) -> Option<DateTime<Utc>> {
+        self.locked_until_dt
+    }
+    pub fn get_created_dt(&self) -> DateTime<Utc> {
+        self.created_dt
+    }
+    pub async fn lookup_by_pk(
+        db: &SqlitePool, pk: i64
+    ) -> Result<Self, RustersError> {
+        query_as::<_, Self>("
+            select
+                pk,
+                username,
+                client_key,
+                is_active,
+                locked_until_dt,
+                created_dt
+            from LoginAttempts
+            where pk = $1"
+        ).bind(pk)
+            .fetch_one(db)
+            .await
+            .quick_match()
+    }
+    /// Active failures counted against `username` under `policy`, newest first
+    pub async fn lookup_active<'a>(
+        db: &SqlitePool,
+        username: &'a str,
+        client_key: Option<&'a str>,
+        policy: &LoginPolicy,
+    ) -> Result<Vec<Self>, RustersError> {
+        query_as::<_, Self>("
+            select
+                pk,
+                username,
+                client_key,
+                is_active,
+                locked_until_dt,
+                created_dt
+            from LoginAttempts
+            where username = $1
+            and ($2 = 0 or client_key is $3)
+            and is_active = 1
+            order by created_dt desc, pk desc"
+        ).bind(username)
+            .bind(policy.per_client)
+            .bind(client_key)
+            .fetch_all(db)
+            .await
+            .quick_match()
+    }
+    /// The time until which `username` is locked, if it currently is
+    pub async fn locked_until<'a>(
+        db: &SqlitePool,
+        username: &'a str,
+        client_key: Option<&'a str>,
+        policy: &LoginPolicy,
+    ) -> Result<Option<DateTime<Utc>>, RustersError> {
+        let now = Utc::now();
+        let attempts = Self::lookup_active(db, username, client_key, policy)
+            .await?;
+        Ok(
+            attempts.into_iter()
+                .filter_map(|a| a.locked_until_dt)
+                .find(|until| *until > now)
+        )
+    }
+    /// Records a failed login and locks the account when the policy is
+    /// exceeded, returning the end of the new lockout if one began
+    pub async fn fail<'a>(
+        db: &SqlitePool,
+        username: &'a str,
+        client_key: Option<&'a str>,
+        policy: &LoginPolicy,
+    ) -> Result<Option<DateTime<Utc>>, RustersError> {
+        let now = Utc::now();
+        let pk = query("
+            insert into LoginAttempts (
+                username,
+                client_key,
+                is_active,
+                created_dt
+            ) values (
+                $1,
+                $2,
+                $3,
+                $4
+            )"
+        ).bind(username)
+            .bind(client_key)
+            .bind(1_i64)
+            .bind(now)
+            .execute(db)
+            .await
+            .quick_match()?
+            .last_insert_rowid();
+        let attempts = Self::lookup_active(db, username, client_key, policy)
+            .await?;
+        let prior_locks = attempts.iter()
+            .filter(|a| a.locked_until_dt.is_some())
+            .count() as i64;
+        let since = attempts.iter()
+            .find(|a| a.locked_until_dt.is_some())
+            .map(|a| a.created_dt)
+            .filter(|dt| *dt > now - policy.window)
+            .unwrap_or(now - policy.window);
+        let failures = attempts.iter()
+            .filter(|a| a.created_dt > since)
+            .count() as i64;
+        if failures < policy.max_failures {
+            return Ok(None);
+        }
+        let until = now + policy.lockout_for(prior_locks);
+        query("
+            update LoginAttempts
+            set locked_until_dt = $1
+            where pk = $2"
+        ).bind(until)
+            .bind(pk)
+            .execute(db)
+            .await
+            .quick_match()?;
+        Ok(Some(until))
+    }
+    /// Forgets the failures counted against `username` under `policy` after
+    /// a successful login. A per-client policy only forgets those of
+    /// `client_key`, so other clients stay locked
+    pub async fn succeed<'a>(
+        db: &SqlitePool,
+        username: &'a str,
+        client_key: Option<&'a str>,
+        policy: &LoginPolicy,
+    ) -> Result<(), RustersError> {
+        query("
+            update LoginAttempts
+            set is_active = 0
+            where username = $1
+            and ($2 = 0 or client_key is $3)
+            and is_active = 1"
+        ).bind(username)
+            .bind(policy.per_client)
+            .bind(client_key)
+            .execute(db)
+            .await
+            .quick_match()?;
+        Ok(())
+    }
+    /// Forgets all failures and lockouts recorded against `username`
+    pub async fn clear<'a>(
+        db: &SqlitePool, username: &'a str
+    ) -> Result<(), RustersError> {
+        query("
+            update LoginAttempts
+            set is_active = 0
+            where username = $1
+            and is_active = 1"
+        ).bind(username)
+            .execute(db)
+            .await
+            .quick_match()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+use {
+    chrono::{
+        DateTime,
+        Duration,
+        Utc,
+    },
+    crate::{
+        error::{
+            MatchRustersError,
+            RustersError,
+        },
+        session::{
+            cookie::SessionCookie,
+            Session,
+        },
+        token::{
+            consumable_token::{
+                consumer::Consumer,
+                ConsumableToken,
+            },
+            Token,
+        },
+        user::User,
+    },
+    sqlx::{ FromRow, SqlitePool, query, query_as, },
+};
+/// A single-use link logging a `User` in without a password, optionally only
+/// on the `Session` it was requested from
+#[derive(FromRow)]
+pub struct MagicLink {
+    pk: i64,
+    user_pk: i64,
+    consumable_token_pk: i64,
+    session_pk: Option<i64>,
+    created_dt: DateTime<Utc>,
+}
+impl MagicLink {
+    pub const CONSUMER: &'static str = "magic_link";
+    pub fn get_pk(&self) -> i64 {
+        self.pk
+    }
+    pub fn get_user_pk(&self) -> i64 {
+        self.user_pk
+    }
+    pub fn get_consumable_token_pk(&self) -> i64 {
+        self.consumable_token_pk
+    }
+    pub fn get_session_pk(&self) -> Option<i64> {
+        self.session_pk
+    }
+    pub fn get_created_dt(&self) -> DateTime<Utc> {
+        self.created_dt
+    }
+    pub fn default_expires() -> Duration {
+        Duration::minutes(15)
+    }
+    pub async fn lookup_by_pk(
+        db: &SqlitePool, pk: i64
+    ) -> Result<Self, RustersError> {
+        query_as::<_, Self>("
+            select
+                pk,
+                user_pk,
+                consumable_token_pk,
+                session_pk,
+                created_dt
+            from MagicLinks
+            where pk = $1"
+        ).bind(pk)
+            .fetch_one(db)
+            .await
+            .quick_match()
+    }
+    pub async fn lookup(
+        db: &SqlitePool, consumable: &ConsumableToken
+    ) -> Result<Self, RustersError> {
+        query_as::<_, Self>("
+            select
+                pk,
+                user_pk,
+                consumable_token_pk,
+                session_pk,
+                created_dt
+            from MagicLinks
+            where consumable_token_pk = $1"
+        ).bind(consumable.get_pk())
+            .fetch_one(db)
+            .await
+            .quick_match()
+    }
+    async fn insert(
+        db: &SqlitePool,
+        user: &User,
+        ttl: Option<Duration>,
+        session: Option<&Session>,
+    ) -> Result<(ConsumableToken, String), RustersError> {
+        let consumer = Consumer::always(db, Self::CONSUMER).await?;
+        let token = Token::basic(
+            db, Some(ttl.unwrap_or_else(Self::default_expires))
+        ).await?;
+        let consumable = ConsumableToken::insert(db, &token, &consumer).await?;
+        query("
+            insert into MagicLinks (
+                user_pk,
+                consumable_token_pk,
+                session_pk,
+                created_dt
+            ) values (
+                $1,
+                $2,
+                $3,
+                $4
+            )"
+        ).bind(user.get_pk())
+            .bind(consumable.get_pk())
+            .bind(session.map(|s| s.get_pk()))
+            .bind(Utc::now())
+            .execute(db)
+            .await
+            .quick_match()?;
+        Ok((consumable, token.get_hash()))
+    }
+    /// Issues a link for `user` redeemable on any session, returning the
+    /// consumable token and the raw secret for the caller to deliver
+    pub async fn issue(
+        db: &SqlitePool, user: &User, ttl: Option<Duration>
+    ) -> Result<(ConsumableToken, String), RustersError> {
+        Self::insert(db, user, ttl, None).await
+    }
+    /// Issues a link for `user` which only logs in `session`, the session the
+    /// link was requested from
+    pub async fn issue_for_session(
+        db: &SqlitePool, user: &User, ttl: Option<Duration>, session: &Session
+    ) -> Result<(ConsumableToken, String), RustersError> {
+        Self::insert(db, user, ttl, Some(session)).await
+    }
+    /// Consumes `secret` and logs `session` in as the user it was issued for.
+    /// A link bound to another session is refused and left unused
+    pub async fn redeem<'a>(
+        db: &SqlitePool, secret: &'a str, session: &Session
+    ) -> Result<User, RustersError> {
+        let token = match Token::possible(db, secret).await? {
+            Some(token) => token,
+            None => return Err(RustersError::InvalidTokenError),
+        };
+        let consumer = Consumer::always(db, Self::CONSUMER).await?;
+        let consumable = ConsumableToken::lookup(db, &token, &consumer)
+            .await
+            .map_err(|_| RustersError::InvalidTokenError)?;
+        let link = Self::lookup(db, &consumable).await?;
+        if let Some(session_pk) = link.session_pk {
+            if session_pk != session.get_pk() {
+                return Err(RustersError::InvalidTokenError);
+            }
+        }
+        let claimed = query("
+            update Tokens
+            set expired_dt = $1
+            where pk = $2
+            and expired_dt > $1"
+        ).bind(Utc::now())
+            .bind(token.get_pk())
+            .execute(db)
+            .await
+            .quick_match()?
+            .rows_affected() == 1;
+        if !claimed {
+            return Err(RustersError::InvalidTokenError);
+        }
+        let user = User::lookup_by_pk(db, link.user_pk).await?;
+        SessionCookie::login(db, session, &user).await?;
+        Ok(user)
+    }
+}
@@ -0,0 +1,138 @@
+use {
+    chrono::{
+        DateTime,
+        Utc,
+    },
+    crate::{
+        error::{
+            MatchRustersError,
+            RustersError,
+        },
+        hash::Secure,
+        user::User,
+    },
+    sqlx::{ FromRow, SqlitePool, query, query_as, },
+};
+/// A password previously held by a `User`, kept so it can't be reused
+#[derive(FromRow)]
+pub struct PasswordHistory {
+    pk: i64,
+    user_pk: i64,
+    password_hash: String,
+    salt: String,
+    created_dt: DateTime<Utc>,
+}
+impl PasswordHistory {
+    pub fn get_pk(&self) -> i64 {
+        self.pk
+    }
+    pub fn get_user_pk(&self) -> i64 {
+        self.user_pk
+    }
+    pub fn get_password_hash(&self) -> String {
+        self.password_hash.clone()
+    }
+    pub fn get_salt(&self) -> String {
+        self.salt.clone()
+    }
+    pub fn get_created_dt(&self) -> DateTime<Utc> {
+        self.created_dt
+    }
+    pub async fn lookup_by_pk(
+        db: &SqlitePool, pk: i64
+    ) -> Result<Self, RustersError> {
+        query_as::<_, Self>("
+            select
+                pk,
+                user_pk,
+                password_hash,
+                salt,
+                created_dt
+            from PasswordHistory
+            where pk = $1"
+        ).bind(pk)
+            .fetch_one(db)
+            .await
+            .quick_match()
+    }
+    /// The `depth` most recent passwords of `user`, newest first
+    pub async fn recent(
+        db: &SqlitePool, user: &User, depth: usize
+    ) -> Result<Vec<Self>, RustersError> {
+        query_as::<_, Self>("
+            select
+                pk,
+                user_pk,
+                password_hash,
+                salt,
+                created_dt
+            from PasswordHistory
+            where user_pk = $1
+            order by pk desc
+            limit $2"
+        ).bind(user.get_pk())
+            .bind(depth as i64)
+            .fetch_all(db)
+            .await
+            .quick_match()
+    }
+    /// Records the current password of `user`
+    pub async fn insert(
+        db: &SqlitePool, user: &User
+    ) -> Result<Self, RustersError> {
+        let pk = query("
+            insert into PasswordHistory (
+                user_pk,
+                password_hash,
+                salt,
+                created_dt
+            ) values (
+                $1,
+                $2,
+                $3,
+                $4
+            )"
+        ).bind(user.get_pk())
+            .bind(user.get_password_hash())
+            .bind(user.get_salt())
+            .bind(Utc::now())
+            .execute(db)
+            .await
+            .quick_match()?
+            .last_insert_rowid();
+        Self::lookup_by_pk(db, pk).await
+    }
+    /// Drops all but the `depth` most recent passwords of `user`
+    pub async fn trim(
+        db: &SqlitePool, user: &User, depth: usize
+    ) -> Result<(), RustersError> {
+        query("
+            delete from PasswordHistory
+            where user_pk = $1
+            and pk not in (
+                select pk
+                from PasswordHistory
+                where user_pk = $1
+                order by pk desc
+                limit $2
+            )"
+        ).bind(user.get_pk())
+            .bind(depth as i64)
+            .execute(db)
+            .await
+            .quick_match()?;
+        Ok(())
+    }
+    /// Whether `password` matches any of the `depth` most recent passwords of
+    /// `user`
+    pub async fn is_reused<'a>(
+        db: &SqlitePool, user: &User, password: &'a str, depth: usize
+    ) -> Result<bool, RustersError> {
+        for prior in Self::recent(db, user, depth).await? {
+            if Secure::validate(password, &prior.password_hash)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
@@ -0,0 +1,164 @@
+pub mod breached_passwords;
+use {
+    crate::error::RustersError,
+    std::sync::Arc,
+};
+const COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+/// A single way in which a password fails a `PasswordPolicy`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PolicyViolation {
+    TooShort { min: usize },
+    TooLong { max: usize },
+    MissingLowercase,
+    MissingUppercase,
+    MissingDigit,
+    MissingSymbol,
+    ContainsUsername,
+    Common,
+    Breached { count: u64 },
+    Reused,
+}
+impl std::fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PolicyViolation::TooShort { min } => {
+                let msg = &format!("must be at least {} characters", min);
+                f.write_str(msg)
+            },
+            PolicyViolation::TooLong { max } => {
+                let msg = &format!("must be at most {} characters", max);
+                f.write_str(msg)
+            },
+            PolicyViolation::MissingLowercase => {
+                f.write_str("must contain a lowercase letter")
+            },
+            PolicyViolation::MissingUppercase => {
+                f.write_str("must contain an uppercase letter")
+            },
+            PolicyViolation::MissingDigit => {
+                f.write_str("must contain a digit")
+            },
+            PolicyViolation::MissingSymbol => {
+                f.write_str("must contain a symbol")
+            },
+            PolicyViolation::ContainsUsername => {
+                f.write_str("must not contain the username")
+            },
+            PolicyViolation::Common => {
+                f.write_str("is too common")
+            },
+            PolicyViolation::Breached { count } => {
+                let msg = &format!("has appeared in {} known breaches", count);
+                f.write_str(msg)
+            },
+            PolicyViolation::Reused => {
+                f.write_str("must not match a recently used password")
+            },
+        }
+    }
+}
+/// An additional rule plugged into a `PasswordPolicy`
+pub trait PasswordCheck: std::fmt::Debug + Send + Sync {
+    fn check(
+        &self, username: &str, password: &str
+    ) -> Result<Option<PolicyViolation>, RustersError>;
+}
+/// Rules a password must satisfy before it is hashed and stored
+#[derive(Clone, Debug)]
+pub struct PasswordPolicy {
+    /// Minimum length in characters
+    pub min_length: usize,
+    /// Maximum length in characters
+    pub max_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    /// Reject passwords containing the username, ignoring case
+    pub disallow_username: bool,
+    /// Reject passwords found in the bundled list of common passwords
+    pub disallow_common: bool,
+    /// Number of previous passwords a new password must not match, counting
+    /// the current one
+    pub history_depth: usize,
+    /// Further checks run after the built in rules
+    pub checks: Vec<Arc<dyn PasswordCheck>>,
+}
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        PasswordPolicy {
+            min_length: 8,
+            max_length: 64,
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+            disallow_username: true,
+            disallow_common: true,
+            history_depth: 5,
+            checks: Vec::new(),
+        }
+    }
+}
+impl PasswordPolicy {
+    fn is_common(password: &str) -> bool {
+        let lower = password.to_lowercase();
+        COMMON_PASSWORDS.lines().any(|c| c == lower)
+    }
+    pub fn with_check(mut self, check: impl PasswordCheck + 'static) -> Self {
+        self.checks.push(Arc::new(check));
+        self
+    }
+    /// Every rule `password` breaks, empty when it is acceptable
+    pub fn check<'a>(
+        &self, username: &'a str, password: &'a str
+    ) -> Result<Vec<PolicyViolation>, RustersError> {
+        let mut violations = Vec::new();
+        let len = password.chars().count();
+        if len < self.min_length {
+            violations.push(PolicyViolation::TooShort { min: self.min_length });
+        }
+        if len > self.max_length {
+            violations.push(PolicyViolation::TooLong { max: self.max_length });
+        }
+        if self.require_lowercase && !password.chars().any(char::is_lowercase) {
+            violations.push(PolicyViolation::MissingLowercase);
+        }
+        if self.require_uppercase && !password.chars().any(char::is_uppercase) {
+            violations.push(PolicyViolation::MissingUppercase);
+        }
+        if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            violations.push(PolicyViolation::MissingDigit);
+        }
+        if self.require_symbol
+            && !password.chars().any(|c| !c.is_alphanumeric() && !c.is_whitespace())
+        {
+            violations.push(PolicyViolation::MissingSymbol);
+        }
+        if self.disallow_username
+            && !username.is_empty()
+            && password.to_lowercase().contains(&username.to_lowercase())
+        {
+            violations.push(PolicyViolation::ContainsUsername);
+        }
+        if self.disallow_common && Self::is_common(password) {
+            violations.push(PolicyViolation::Common);
+        }
+        for check in &self.checks {
+            if let Some(violation) = check.check(username, password)? {
+                violations.push(violation);
+            }
+        }
+        Ok(violations)
+    }
+    pub fn enforce<'a>(
+        &self, username: &'a str, password: &'a str
+    ) -> Result<(), RustersError> {
+        let violations = self.check(username, password)?;
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(RustersError::WeakPassword(violations))
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use {
+    crate::{
+        error::{
+            MatchRustersError,
+            RustersError,
+        },
+        user::password_policy::{
+            PasswordCheck,
+            PolicyViolation,
+        },
+    },
+    sha1::{
+        Digest,
+        Sha1,
+    },
+    std::{
+        fs::File,
+        io::{
+            BufRead,
+            BufReader,
+            Seek,
+            SeekFrom,
+        },
+        path::PathBuf,
+    },
+};
+/// A locally downloaded Pwned Passwords file: one `SHA1:COUNT` line per
+/// breached password, sorted by hash, searched without loading it into memory
+#[derive(Clone, Debug)]
+pub struct BreachedPasswords {
+    path: PathBuf,
+    min_count: u64,
+}
+impl BreachedPasswords {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        BreachedPasswords { path: path.into(), min_count: 1, }
+    }
+    /// Only reject passwords seen in at least `min_count` breaches
+    pub fn with_min_count(mut self, min_count: u64) -> Self {
+        self.min_count = min_count;
+        self
+    }
+    pub fn get_path(&self) -> PathBuf {
+        self.path.clone()
+    }
+    pub fn get_min_count(&self) -> u64 {
+        self.min_count
+    }
+    pub fn sha1_hex(password: impl AsRef<str>) -> String {
+        Sha1::digest(password.as_ref().as_bytes())
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect()
+    }
+    fn read_line_at(
+        reader: &mut BufReader<File>, pos: u64
+    ) -> Result<(u64, String), RustersError> {
+        let mut skipped = Vec::new();
+        let start = if pos == 0 {
+            reader.seek(SeekFrom::Start(0)).quick_match()?;
+            0
+        } else {
+            reader.seek(SeekFrom::Start(pos - 1)).quick_match()?;
+            pos - 1 + reader.read_until(b'\n', &mut skipped).quick_match()? as u64
+        };
+        let mut line = String::new();
+        reader.read_line(&mut line).quick_match()?;
+        Ok((start, line))
+    }
+    /// The breach count recorded for `password`, if it appears in the file
+    pub fn lookup(
+        &self, password: impl AsRef<str>
+    ) -> Result<Option<u64>, RustersError> {
+        let target = Self::sha1_hex(password);
+        let file = File::open(&self.path).quick_match()?;
+        let mut lo = 0;
+        let mut hi = file.metadata().quick_match()?.len();
+        let mut reader = BufReader::new(file);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let (start, line) = Self::read_line_at(&mut reader, mid)?;
+            if start >= hi || line.is_empty() {
+                hi = mid;
+                continue;
+            }
+            let line_len = line.len() as u64;
+            let line = line.trim_end();
+            let (hash, count) = line.split_once(':').unwrap_or((line, "0"));
+            match hash.to_ascii_uppercase().as_str().cmp(target.as_str()) {
+                std::cmp::Ordering::Less => lo = start + line_len,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => {
+                    return Ok(Some(count.trim().parse().unwrap_or(1)));
+                },
+            }
+        }
+        Ok(None)
+    }
+}
+impl PasswordCheck for BreachedPasswords {
+    fn check(
+        &self, _username: &str, password: &str
+    ) -> Result<Option<PolicyViolation>, RustersError> {
+        Ok(match self.lookup(password)? {
+            Some(count) if count >= self.min_count => {
+                Some(PolicyViolation::Breached { count })
+            },
+            _ => None,
+        })
+    }
+}
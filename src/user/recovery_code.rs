@@ -0,0 +1,190 @@
+use {
+    chrono::{
+        DateTime,
+        Duration,
+        Utc,
+    },
+    crate::{
+        error::{
+            MatchRustersError,
+            RustersError,
+        },
+        hash::{
+            Hash,
+            Secure,
+        },
+        token::Token,
+        user::User,
+    },
+    rand::Rng,
+    sqlx::{ FromRow, SqlitePool, query, query_as, },
+};
+const CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+/// A single-use code letting a two-factor `User` log in without their
+/// authenticator. Each code is a selector, used to find the stored row, and a
+/// secret held as a `Secure` `Token` which is expired once redeemed
+#[derive(FromRow)]
+pub struct RecoveryCode {
+    pk: i64,
+    user_pk: i64,
+    token_pk: i64,
+    selector: String,
+    created_dt: DateTime<Utc>,
+}
+impl RecoveryCode {
+    pub const BATCH_SIZE: usize = 10;
+    const SELECTOR_LEN: usize = 4;
+    const SECRET_LEN: usize = 8;
+    pub fn get_pk(&self) -> i64 {
+        self.pk
+    }
+    pub fn get_user_pk(&self) -> i64 {
+        self.user_pk
+    }
+    pub fn get_token_pk(&self) -> i64 {
+        self.token_pk
+    }
+    pub fn get_selector(&self) -> String {
+        self.selector.clone()
+    }
+    pub fn get_created_dt(&self) -> DateTime<Utc> {
+        self.created_dt
+    }
+    /// Recovery codes stay valid until redeemed or regenerated
+    pub fn default_expires() -> Duration {
+        Duration::days(365 * 100)
+    }
+    fn random_part(len: usize) -> String {
+        let mut rng = rand::thread_rng();
+        (0..len)
+            .map(|_| CODE_ALPHABET[rng.gen_range(0..CODE_ALPHABET.len())] as char)
+            .collect()
+    }
+    /// Codes of `user` which have not been redeemed or replaced
+    pub async fn lookup_remaining(
+        db: &SqlitePool, user: &User
+    ) -> Result<Vec<Self>, RustersError> {
+        query_as::<_, Self>("
+            select
+                r.pk,
+                r.user_pk,
+                r.token_pk,
+                r.selector,
+                r.created_dt
+            from RecoveryCodes as r
+            join Tokens as t
+            on t.pk = r.token_pk
+            where r.user_pk = $1
+            and t.expired_dt > $2"
+        ).bind(user.get_pk())
+            .bind(Utc::now())
+            .fetch_all(db)
+            .await
+            .quick_match()
+    }
+    /// Invalidates every outstanding code of `user`
+    pub async fn revoke(
+        db: &SqlitePool, user: &User
+    ) -> Result<(), RustersError> {
+        let now = Utc::now();
+        query("
+            update Tokens
+            set expired_dt = $1
+            where expired_dt > $1
+            and pk in (
+                select token_pk
+                from RecoveryCodes
+                where user_pk = $2
+            )"
+        ).bind(now)
+            .bind(user.get_pk())
+            .execute(db)
+            .await
+            .quick_match()?;
+        Ok(())
+    }
+    /// Replaces any previous batch of `user` with a new one, returning the raw
+    /// codes. These are only available now and must be shown to the user
+    pub async fn generate(
+        db: &SqlitePool, user: &User
+    ) -> Result<Vec<String>, RustersError> {
+        Self::revoke(db, user).await?;
+        let mut codes = Vec::with_capacity(Self::BATCH_SIZE);
+        let mut selectors = Vec::with_capacity(Self::BATCH_SIZE);
+        for _ in 0..Self::BATCH_SIZE {
+            // selectors are short enough to collide, so draw again until each
+            // code of the batch has its own
+            let selector = loop {
+                let selector = Self::random_part(Self::SELECTOR_LEN);
+                if !selectors.contains(&selector) {
+                    break selector;
+                }
+            };
+            let secret = Self::random_part(Self::SECRET_LEN);
+            let hash = Secure::from_string(&secret)?;
+            let token = Token::insert(db, hash, Some(Self::default_expires()))
+                .await?;
+            query("
+                insert into RecoveryCodes (
+                    user_pk,
+                    token_pk,
+                    selector,
+                    created_dt
+                ) values (
+                    $1,
+                    $2,
+                    $3,
+                    $4
+                )"
+            ).bind(user.get_pk())
+                .bind(token.get_pk())
+                .bind(&selector)
+                .bind(Utc::now())
+                .execute(db)
+                .await
+                .quick_match()?;
+            codes.push(format!("{}-{}", selector, secret));
+            selectors.push(selector);
+        }
+        Ok(codes)
+    }
+    /// Consumes `code` for `user`, failing if it is unknown or already used
+    pub async fn redeem<'a>(
+        db: &SqlitePool, user: &User, code: &'a str
+    ) -> Result<(), RustersError> {
+        let code = code.trim().to_lowercase();
+        let (selector, secret) = match code.split_once('-') {
+            Some(parts) => parts,
+            None => return Err(RustersError::InvalidSecondFactorError),
+        };
+        let remaining = Self::lookup_remaining(db, user).await?;
+        let mut found = None;
+        for recovery in remaining.iter().filter(|r| r.selector == selector) {
+            let token = Token::lookup_by_pk(db, recovery.token_pk).await?;
+            if Secure::validate(secret, token.get_hash())? {
+                found = Some(token);
+                break;
+            }
+        }
+        let token = match found {
+            Some(token) => token,
+            None => return Err(RustersError::InvalidSecondFactorError),
+        };
+        let consumed = query("
+            update Tokens
+            set expired_dt = $1
+            where pk = $2
+            and expired_dt > $1"
+        ).bind(Utc::now())
+            .bind(token.get_pk())
+            .execute(db)
+            .await
+            .quick_match()?
+            .rows_affected() > 0;
+        if consumed {
+            Ok(())
+        } else {
+            Err(RustersError::InvalidSecondFactorError)
+        }
+    }
+}
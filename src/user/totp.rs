@@ -0,0 +1,298 @@
+use {
+    base32::Alphabet,
+    chrono::{
+        DateTime,
+        Utc,
+    },
+    crate::{
+        error::{
+            MatchRustersError,
+            RustersError,
+        },
+        user::User,
+    },
+    hmac::{
+        Hmac,
+        Mac,
+    },
+    rand::RngCore,
+    sha1::Sha1,
+    sqlx::{ FromRow, SqlitePool, query, query_as, },
+};
+const SECRET_ALPHABET: Alphabet = Alphabet::RFC4648 { padding: false };
+/// An RFC 6238 time-based one-time password secret belonging to a `User`
+#[derive(FromRow)]
+pub struct Totp {
+    pk: i64,
+    user_pk: i64,
+    secret: String,
+    is_confirmed: bool,
+    last_step: Option<i64>,
+    is_active: bool,
+    created_dt: DateTime<Utc>,
+}
+impl Totp {
+    pub const DIGITS: u32 = 6;
+    pub const PERIOD: i64 = 30;
+    pub const SECRET_BYTES: usize = 20;
+    /// Steps either side of the current one that are still accepted
+    pub const DEFAULT_SKEW: i64 = 1;
+    pub fn get_pk(&self) -> i64 {
+        self.pk
+    }
+    pub fn get_user_pk(&self) -> i64 {
+        self.user_pk
+    }
+    /// The base32 encoded shared secret
+    pub fn get_secret(&self) -> String {
+        self.secret.clone()
+    }
+    pub fn get_is_confirmed(&self) -> bool {
+        self.is_confirmed
+    }
+    pub fn get_last_step(&self) -> Option<i64> {
+        self.last_step
+    }
+    pub fn get_is_active(&self) -> bool {
+        self.is_active
+    }
+    pub fn get_created_dt(&self) -> DateTime<Utc> {
+        self.created_dt
+    }
+    pub async fn lookup_by_pk(
+        db: &SqlitePool, pk: i64
+    ) -> Result<Self, RustersError> {
+        query_as::<_, Self>("
+            select
+                pk,
+                user_pk,
+                secret,
+                is_confirmed,
+                last_step,
+                is_active,
+                created_dt
+            from Totps
+            where pk = $1
+            and is_active = 1"
+        ).bind(pk)
+            .fetch_one(db)
+            .await
+            .quick_match()
+    }
+    async fn lookup_by_state(
+        db: &SqlitePool, user: &User, is_confirmed: bool
+    ) -> Result<Option<Self>, RustersError> {
+        let totps = query_as::<_, Self>("
+            select
+                pk,
+                user_pk,
+                secret,
+                is_confirmed,
+                last_step,
+                is_active,
+                created_dt
+            from Totps
+            where user_pk = $1
+            and is_confirmed = $2
+            and is_active = 1
+            order by pk desc"
+        ).bind(user.get_pk())
+            .bind(is_confirmed)
+            .fetch_all(db)
+            .await
+            .quick_match()?;
+        Ok(totps.into_iter().nth(0))
+    }
+    /// The confirmed secret of `user`, if they have two-factor enabled
+    pub async fn lookup(
+        db: &SqlitePool, user: &User
+    ) -> Result<Option<Self>, RustersError> {
+        Self::lookup_by_state(db, user, true).await
+    }
+    /// An enrollment of `user` that is still waiting to be confirmed
+    pub async fn lookup_pending(
+        db: &SqlitePool, user: &User
+    ) -> Result<Option<Self>, RustersError> {
+        Self::lookup_by_state(db, user, false).await
+    }
+    pub async fn is_enabled(
+        db: &SqlitePool, user: &User
+    ) -> Result<bool, RustersError> {
+        Ok(Self::lookup(db, user).await?.is_some())
+    }
+    /// Starts enrolling `user` with a fresh secret, replacing any enrollment
+    /// not yet confirmed. The secret is not used for login until `confirm`
+    pub async fn enroll(
+        db: &SqlitePool, user: &User
+    ) -> Result<Self, RustersError> {
+        query("
+            update Totps
+            set is_active = 0
+            where user_pk = $1
+            and is_confirmed = 0
+            and is_active = 1"
+        ).bind(user.get_pk())
+            .execute(db)
+            .await
+            .quick_match()?;
+        let mut bytes = [0_u8; Self::SECRET_BYTES];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let secret = base32::encode(SECRET_ALPHABET, &bytes);
+        let pk = query("
+            insert into Totps (
+                user_pk,
+                secret,
+                is_confirmed,
+                is_active,
+                created_dt
+            ) values (
+                $1,
+                $2,
+                $3,
+                $4,
+                $5
+            )"
+        ).bind(user.get_pk())
+            .bind(secret)
+            .bind(0_i64)
+            .bind(1_i64)
+            .bind(Utc::now())
+            .execute(db)
+            .await
+            .quick_match()?
+            .last_insert_rowid();
+        Self::lookup_by_pk(db, pk).await
+    }
+    /// The `otpauth://` URI authenticator apps read from a QR code
+    pub fn provisioning_uri<'a>(&self, issuer: &'a str, user: &User) -> String {
+        let issuer = urlencoding::encode(issuer);
+        format!(
+            "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+            issuer,
+            urlencoding::encode(&user.get_username()),
+            self.secret,
+            issuer,
+            Self::DIGITS,
+            Self::PERIOD,
+        )
+    }
+    fn step_at(time: DateTime<Utc>) -> i64 {
+        time.timestamp().div_euclid(Self::PERIOD)
+    }
+    fn code_for_step<'a>(
+        secret: &'a str, step: i64
+    ) -> Result<String, RustersError> {
+        let key = base32::decode(SECRET_ALPHABET, secret)
+            .ok_or(RustersError::InvalidSecondFactorError)?;
+        let mut mac = Hmac::<Sha1>::new_from_slice(&key)
+            .map_err(|_| RustersError::InvalidSecondFactorError)?;
+        mac.update(&step.to_be_bytes());
+        let digest = mac.finalize().into_bytes();
+        let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([
+            digest[offset] & 0x7f,
+            digest[offset + 1],
+            digest[offset + 2],
+            digest[offset + 3],
+        ]);
+        Ok(format!(
+            "{:0width$}",
+            binary % 10_u32.pow(Self::DIGITS),
+            width = Self::DIGITS as usize
+        ))
+    }
+    /// The code an authenticator holding the base32 `secret` would display
+    /// at `time`
+    pub fn generate<'a>(
+        secret: &'a str, time: DateTime<Utc>
+    ) -> Result<String, RustersError> {
+        Self::code_for_step(secret, Self::step_at(time))
+    }
+    pub fn code_at(&self, time: DateTime<Utc>) -> Result<String, RustersError> {
+        Self::generate(&self.secret, time)
+    }
+    /// Compares two codes without stopping at the first differing digit
+    fn codes_match<'a>(expected: &'a str, given: &'a str) -> bool {
+        expected.len() == given.len()
+            && expected.bytes()
+                .zip(given.bytes())
+                .fold(0_u8, |diff, (a, b)| diff | (a ^ b)) == 0
+    }
+    /// Checks `code` against the steps within `skew` of now and claims the
+    /// matching step so the same code can't be used twice
+    pub async fn verify<'a>(
+        &mut self, db: &SqlitePool, code: &'a str, skew: Option<i64>
+    ) -> Result<(), RustersError> {
+        let skew = skew.unwrap_or(Self::DEFAULT_SKEW).max(0);
+        let now = Self::step_at(Utc::now());
+        let mut matched = None;
+        for step in (now - skew)..=(now + skew) {
+            if Self::codes_match(&Self::code_for_step(&self.secret, step)?, code.trim()) {
+                matched = Some(step);
+            }
+        }
+        let step = match matched {
+            Some(step) => step,
+            None => return Err(RustersError::InvalidSecondFactorError),
+        };
+        let claimed = query("
+            update Totps
+            set last_step = $1
+            where pk = $2
+            and (last_step is null or last_step < $1)"
+        ).bind(step)
+            .bind(self.pk)
+            .execute(db)
+            .await
+            .quick_match()?
+            .rows_affected() > 0;
+        if !claimed {
+            return Err(RustersError::InvalidSecondFactorError);
+        }
+        self.last_step = Some(step);
+        Ok(())
+    }
+    /// Completes enrollment once the user proves their authenticator produces
+    /// matching codes, replacing any previously confirmed secret
+    pub async fn confirm<'a>(
+        &mut self, db: &SqlitePool, code: &'a str, skew: Option<i64>
+    ) -> Result<(), RustersError> {
+        self.verify(db, code, skew).await?;
+        query("
+            update Totps
+            set is_active = 0
+            where user_pk = $1
+            and pk <> $2
+            and is_active = 1"
+        ).bind(self.user_pk)
+            .bind(self.pk)
+            .execute(db)
+            .await
+            .quick_match()?;
+        query("
+            update Totps
+            set is_confirmed = 1
+            where pk = $1"
+        ).bind(self.pk)
+            .execute(db)
+            .await
+            .quick_match()?;
+        self.is_confirmed = true;
+        Ok(())
+    }
+    /// Turns two-factor authentication off for `user`
+    pub async fn disable(
+        db: &SqlitePool, user: &User
+    ) -> Result<(), RustersError> {
+        query("
+            update Totps
+            set is_active = 0
+            where user_pk = $1
+            and is_active = 1"
+        ).bind(user.get_pk())
+            .execute(db)
+            .await
+            .quick_match()?;
+        Ok(())
+    }
+}